@@ -0,0 +1,310 @@
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use external_command::{self, ExternalCommandError};
+
+/// File listing the genomes given to Bifrost as sequencing data (`-s`).
+pub const SEQUENCE_LIST_FILE_NAME: &str = "genome_list.txt";
+/// File listing the genomes given to Bifrost as references (`-r`).
+pub const REFERENCE_LIST_FILE_NAME: &str = "rep_genome_list.txt";
+/// Prefix of the graph files written into the output directory.
+pub const GRAPH_PREFIX: &str = "bifrost_graph";
+
+/// The Bifrost subcommands which can be run.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BifrostMode {
+    /// Build a new graph from genomes.
+    Build,
+    /// Add genomes to an existing graph.
+    Update,
+    /// Query sequences against an existing coloured graph.
+    Query,
+}
+
+/// Assembles the arguments of a Bifrost run. Genome lists are written into
+/// the output directory, and the graph is written there with the
+/// `bifrost_graph` prefix.
+#[derive(Debug, Clone)]
+pub struct BifrostBuilder {
+    mode: BifrostMode,
+    output_directory: PathBuf,
+    sequence_genomes: Vec<String>,
+    reference_genomes: Vec<String>,
+    graph_file: Option<PathBuf>,
+    colors_file: Option<PathBuf>,
+    query_files: Vec<PathBuf>,
+    query_kmer_ratio: Option<f64>,
+    kmer_length: Option<usize>,
+    minimizer_length: Option<usize>,
+    threads: usize,
+    colors: bool,
+    verbose: bool,
+}
+
+impl BifrostBuilder {
+    fn new<P: AsRef<Path>>(mode: BifrostMode, output_directory: P) -> BifrostBuilder {
+        BifrostBuilder {
+            mode,
+            output_directory: output_directory.as_ref().to_path_buf(),
+            sequence_genomes: vec!(),
+            reference_genomes: vec!(),
+            graph_file: None,
+            colors_file: None,
+            query_files: vec!(),
+            query_kmer_ratio: None,
+            kmer_length: None,
+            minimizer_length: None,
+            threads: 1,
+            colors: false,
+            verbose: false,
+        }
+    }
+
+    /// Build a new graph in the given output directory.
+    pub fn build<P: AsRef<Path>>(output_directory: P) -> BifrostBuilder {
+        BifrostBuilder::new(BifrostMode::Build, output_directory)
+    }
+
+    /// Add genomes to the given graph, writing the updated graph into the
+    /// given output directory.
+    pub fn update<P: AsRef<Path>, G: AsRef<Path>>(output_directory: P, graph_file: G) -> BifrostBuilder {
+        let mut builder = BifrostBuilder::new(BifrostMode::Update, output_directory);
+        builder.graph_file = Some(graph_file.as_ref().to_path_buf());
+        builder
+    }
+
+    /// Query sequences against the given graph and its colours, writing the
+    /// results into the given output directory.
+    pub fn query<P: AsRef<Path>, G: AsRef<Path>, C: AsRef<Path>>(
+        output_directory: P, graph_file: G, colors_file: C) -> BifrostBuilder {
+        let mut builder = BifrostBuilder::new(BifrostMode::Query, output_directory);
+        builder.graph_file = Some(graph_file.as_ref().to_path_buf());
+        builder.colors_file = Some(colors_file.as_ref().to_path_buf());
+        builder
+    }
+
+    /// Genomes whose k-mers are filtered for errors, as for sequencing reads.
+    pub fn sequence_genomes(mut self, genomes: Vec<String>) -> BifrostBuilder {
+        self.sequence_genomes = genomes;
+        self
+    }
+
+    /// Genomes whose k-mers are all kept.
+    pub fn reference_genomes(mut self, genomes: Vec<String>) -> BifrostBuilder {
+        self.reference_genomes = genomes;
+        self
+    }
+
+    /// Colours file of the graph being updated.
+    pub fn colors_file<P: AsRef<Path>>(mut self, colors_file: P) -> BifrostBuilder {
+        self.colors_file = Some(colors_file.as_ref().to_path_buf());
+        self
+    }
+
+    /// Files of sequences to query.
+    pub fn query_files(mut self, query_files: Vec<PathBuf>) -> BifrostBuilder {
+        self.query_files = query_files;
+        self
+    }
+
+    /// Ratio of k-mers from a query which must occur in the graph for it to
+    /// be reported as present.
+    pub fn query_kmer_ratio(mut self, ratio: f64) -> BifrostBuilder {
+        self.query_kmer_ratio = Some(ratio);
+        self
+    }
+
+    pub fn kmer_length(mut self, kmer_length: usize) -> BifrostBuilder {
+        self.kmer_length = Some(kmer_length);
+        self
+    }
+
+    pub fn minimizer_length(mut self, minimizer_length: usize) -> BifrostBuilder {
+        self.minimizer_length = Some(minimizer_length);
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> BifrostBuilder {
+        self.threads = threads;
+        self
+    }
+
+    /// Whether to colour the graph by the genome each k-mer came from.
+    pub fn colors(mut self, colors: bool) -> BifrostBuilder {
+        self.colors = colors;
+        self
+    }
+
+    pub fn verbose(mut self, verbose: bool) -> BifrostBuilder {
+        self.verbose = verbose;
+        self
+    }
+
+    pub fn sequence_list_path(&self) -> PathBuf {
+        self.output_directory.join(SEQUENCE_LIST_FILE_NAME)
+    }
+
+    pub fn reference_list_path(&self) -> PathBuf {
+        self.output_directory.join(REFERENCE_LIST_FILE_NAME)
+    }
+
+    pub fn graph_prefix(&self) -> PathBuf {
+        self.output_directory.join(GRAPH_PREFIX)
+    }
+
+    /// The arguments passed to Bifrost, each as a separate entry.
+    pub fn args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec!();
+        args.push(match self.mode {
+            BifrostMode::Build => "build",
+            BifrostMode::Update => "update",
+            BifrostMode::Query => "query",
+        }.into());
+        if let Some(ref graph_file) = self.graph_file {
+            args.push("-g".into());
+            args.push(graph_file.into());
+        }
+        if let Some(ref colors_file) = self.colors_file {
+            args.push("-f".into());
+            args.push(colors_file.into());
+        }
+        if self.mode != BifrostMode::Query {
+            if !self.sequence_genomes.is_empty() {
+                args.push("-s".into());
+                args.push(self.sequence_list_path().into());
+            }
+            if !self.reference_genomes.is_empty() {
+                args.push("-r".into());
+                args.push(self.reference_list_path().into());
+            }
+        }
+        for query_file in &self.query_files {
+            args.push("-q".into());
+            args.push(query_file.into());
+        }
+        if let Some(ratio) = self.query_kmer_ratio {
+            args.push("-e".into());
+            args.push(ratio.to_string().into());
+        }
+        args.push("-o".into());
+        args.push(self.graph_prefix().into());
+        if let Some(kmer_length) = self.kmer_length {
+            args.push("-k".into());
+            args.push(kmer_length.to_string().into());
+        }
+        if let Some(minimizer_length) = self.minimizer_length {
+            args.push("-m".into());
+            args.push(minimizer_length.to_string().into());
+        }
+        args.push("-t".into());
+        args.push(self.threads.to_string().into());
+        if self.colors && self.mode == BifrostMode::Build {
+            args.push("-c".into());
+        }
+        if self.verbose {
+            args.push("-v".into());
+        }
+        args
+    }
+
+    /// Write the genome list files into the output directory.
+    pub fn write_genome_lists(&self) -> io::Result<()> {
+        fs::create_dir_all(&self.output_directory)?;
+        if !self.sequence_genomes.is_empty() {
+            write_genome_list(self.sequence_list_path(), &self.sequence_genomes)?;
+        }
+        if !self.reference_genomes.is_empty() {
+            write_genome_list(self.reference_list_path(), &self.reference_genomes)?;
+        }
+        Ok(())
+    }
+
+    pub fn command(&self) -> Command {
+        let mut command = Command::new("Bifrost");
+        command.args(self.args());
+        command
+    }
+
+    /// Write the genome lists and run Bifrost, returning an error if it
+    /// fails.
+    pub fn run(&self) -> Result<Output, ExternalCommandError> {
+        self.write_genome_lists()?;
+        external_command::run_command(&mut self.command(), "Bifrost")
+    }
+}
+
+/// Write one genome path per line, as Bifrost expects of `-s` and `-r`
+/// arguments which are not themselves FASTA files.
+pub fn write_genome_list<P: AsRef<Path>>(path: P, genomes: &[String]) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(fs::File::create(path)?);
+    for genome in genomes {
+        writeln!(writer, "{}", genome)?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn strings(args: Vec<OsString>) -> Vec<String> {
+        args.into_iter().map(|a| a.into_string().unwrap()).collect()
+    }
+
+    #[test]
+    fn test_build_args(){
+        let builder = BifrostBuilder::build("out")
+            .sequence_genomes(vec!["a.fna".to_string()])
+            .reference_genomes(vec!["b.fna".to_string()])
+            .kmer_length(31)
+            .minimizer_length(23)
+            .threads(4)
+            .colors(true)
+            .verbose(true);
+        assert_eq!(
+            vec!["build", "-s", "out/genome_list.txt", "-r", "out/rep_genome_list.txt",
+                 "-o", "out/bifrost_graph", "-k", "31", "-m", "23", "-t", "4", "-c", "-v"],
+            strings(builder.args()));
+
+        let builder = BifrostBuilder::build("out")
+            .reference_genomes(vec!["b.fna".to_string()]);
+        assert_eq!(
+            vec!["build", "-r", "out/rep_genome_list.txt", "-o", "out/bifrost_graph", "-t", "1"],
+            strings(builder.args()));
+    }
+
+    #[test]
+    fn test_update_and_query_args(){
+        let builder = BifrostBuilder::update("out", "old/graph.gfa")
+            .colors_file("old/graph.bfg_colors")
+            .sequence_genomes(vec!["a.fna".to_string()])
+            .colors(true);
+        assert_eq!(
+            vec!["update", "-g", "old/graph.gfa", "-f", "old/graph.bfg_colors",
+                 "-s", "out/genome_list.txt", "-o", "out/bifrost_graph", "-t", "1"],
+            strings(builder.args()));
+
+        let builder = BifrostBuilder::query("out", "g.gfa", "g.bfg_colors")
+            .query_files(vec![PathBuf::from("reads.fq")])
+            .query_kmer_ratio(0.8)
+            .threads(2);
+        assert_eq!(
+            vec!["query", "-g", "g.gfa", "-f", "g.bfg_colors", "-q", "reads.fq",
+                 "-e", "0.8", "-o", "out/bifrost_graph", "-t", "2"],
+            strings(builder.args()));
+    }
+
+    #[test]
+    fn test_write_genome_lists(){
+        let dir = ::tempfile::tempdir().unwrap();
+        let builder = BifrostBuilder::build(dir.path().join("bifrost"))
+            .sequence_genomes(vec!["a.fna".to_string(), "b.fna".to_string()]);
+        builder.write_genome_lists().unwrap();
+        assert_eq!("a.fna\nb.fna\n", fs::read_to_string(builder.sequence_list_path()).unwrap());
+        assert!(!builder.reference_list_path().exists());
+    }
+}
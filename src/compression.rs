@@ -0,0 +1,92 @@
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use bzip2::read::MultiBzDecoder;
+use flate2::read::MultiGzDecoder;
+use xz2::read::XzDecoder;
+
+/// Compression formats recognised from the leading bytes of a file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum CompressionType {
+    Uncompressed,
+    /// gzip, including multi-member files such as bgzip output.
+    Gzip,
+    /// bzip2, including multi-stream files such as pbzip2 output.
+    Bzip2,
+    Xz,
+}
+
+const GZIP_MAGIC: &[u8] = &[0x1f, 0x8b];
+const BZIP2_MAGIC: &[u8] = b"BZh";
+const XZ_MAGIC: &[u8] = &[0xfd, b'7', b'z', b'X', b'Z', 0x00];
+
+/// Extensions of compressed files, stripped when deriving genome names.
+pub const COMPRESSED_EXTENSIONS: &[&str] = &[".gz", ".bgz", ".bz2", ".xz"];
+
+impl CompressionType {
+    /// Determine the compression type from the first bytes of a stream.
+    pub fn from_magic_bytes(bytes: &[u8]) -> CompressionType {
+        if bytes.starts_with(GZIP_MAGIC) {
+            CompressionType::Gzip
+        } else if bytes.starts_with(BZIP2_MAGIC) {
+            CompressionType::Bzip2
+        } else if bytes.starts_with(XZ_MAGIC) {
+            CompressionType::Xz
+        } else {
+            CompressionType::Uncompressed
+        }
+    }
+}
+
+/// Wrap a reader so that gzip, bgzip, bzip2 or xz input is decompressed
+/// transparently, as determined by its magic bytes. Uncompressed input is
+/// passed through unchanged.
+pub fn decompressing_reader<R: Read + Send + 'static>(reader: R)
+    -> io::Result<Box<dyn Read + Send>> {
+    let mut buffered = io::BufReader::new(reader);
+    let compression_type = {
+        let buffer = buffered.fill_buf()?;
+        CompressionType::from_magic_bytes(buffer)
+    };
+    Ok(match compression_type {
+        CompressionType::Uncompressed => Box::new(buffered),
+        CompressionType::Gzip => Box::new(MultiGzDecoder::new(buffered)),
+        CompressionType::Bzip2 => Box::new(MultiBzDecoder::new(buffered)),
+        CompressionType::Xz => Box::new(XzDecoder::new_multi_decoder(buffered)),
+    })
+}
+
+/// Open a file for reading, decompressing it if necessary.
+pub fn open_file<P: AsRef<Path>>(path: P) -> io::Result<Box<dyn Read + Send>> {
+    fs::File::open(path).and_then(decompressing_reader)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_from_magic_bytes(){
+        assert_eq!(CompressionType::Gzip, CompressionType::from_magic_bytes(&[0x1f, 0x8b, 0x08]));
+        assert_eq!(CompressionType::Bzip2, CompressionType::from_magic_bytes(b"BZh91AY"));
+        assert_eq!(CompressionType::Xz, CompressionType::from_magic_bytes(&[0xfd, b'7', b'z', b'X', b'Z', 0x00, 0x00]));
+        assert_eq!(CompressionType::Uncompressed, CompressionType::from_magic_bytes(b">seq1\nAA\n"));
+        assert_eq!(CompressionType::Uncompressed, CompressionType::from_magic_bytes(b""));
+    }
+
+    #[test]
+    fn test_open_compressed_files(){
+        for file in ["tests/data/genome1.fna",
+                     "tests/data/genome1.fna.gz",
+                     "tests/data/genome1_multimember.fna.gz",
+                     "tests/data/genome1.fna.bz2",
+                     "tests/data/genome1_multistream.fna.bz2",
+                     "tests/data/genome1.fna.xz"].iter() {
+            let mut contents = String::new();
+            open_file(file).unwrap().read_to_string(&mut contents).unwrap();
+            assert_eq!(">seq1\naa\n>seq2\ntt\n", contents, "{}", file);
+        }
+    }
+}
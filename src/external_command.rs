@@ -0,0 +1,125 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+use std::process::{Child, Command, ExitStatus, Output};
+
+/// Number of lines at the end of a failed command's stderr which are kept for
+/// reporting.
+pub const STDERR_TAIL_LINES: usize = 20;
+
+/// Errors from running an external program such as kallisto or Bifrost.
+#[derive(Debug)]
+pub enum ExternalCommandError {
+    /// The program could not be found on the PATH.
+    NotFound {
+        program: String,
+    },
+    /// The program ran but exited unsuccessfully.
+    Failed {
+        program: String,
+        status: ExitStatus,
+        stderr_tail: String,
+    },
+    /// Preparing input for the program, or running it, failed.
+    Io(io::Error),
+}
+
+impl fmt::Display for ExternalCommandError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            ExternalCommandError::NotFound { ref program } => write!(
+                f, "Could not find {} on the PATH, is it installed?", program),
+            ExternalCommandError::Failed { ref program, ref status, ref stderr_tail } => write!(
+                f, "{} failed with {}, stderr ends with:\n{}", program, status, stderr_tail),
+            ExternalCommandError::Io(ref e) => write!(f, "{}", e),
+        }
+    }
+}
+
+impl Error for ExternalCommandError {
+    fn description(&self) -> &str {
+        match *self {
+            ExternalCommandError::NotFound { .. } => "external program not found",
+            ExternalCommandError::Failed { .. } => "external program failed",
+            ExternalCommandError::Io(_) => "I/O error running external program",
+        }
+    }
+}
+
+impl From<io::Error> for ExternalCommandError {
+    fn from(e: io::Error) -> ExternalCommandError {
+        ExternalCommandError::Io(e)
+    }
+}
+
+fn spawn_error(program: &str, e: io::Error) -> ExternalCommandError {
+    match e.kind() {
+        io::ErrorKind::NotFound => ExternalCommandError::NotFound { program: program.to_string() },
+        _ => ExternalCommandError::Io(e),
+    }
+}
+
+/// Return the last `num_lines` lines of a program's stderr.
+pub fn stderr_tail(stderr: &[u8], num_lines: usize) -> String {
+    let stderr = String::from_utf8_lossy(stderr);
+    let lines: Vec<&str> = stderr.trim_end().lines().collect();
+    let start = if lines.len() > num_lines { lines.len() - num_lines } else { 0 };
+    lines[start..].join("\n")
+}
+
+/// Run a command to completion, capturing its output, and return an error
+/// unless it exited successfully.
+pub fn run_command(command: &mut Command, program: &str) -> Result<Output, ExternalCommandError> {
+    let output = command.output().map_err(|e| spawn_error(program, e))?;
+    check_output(program, output)
+}
+
+/// Start a command without waiting for it to finish.
+pub fn spawn_command(command: &mut Command, program: &str) -> Result<Child, ExternalCommandError> {
+    command.spawn().map_err(|e| spawn_error(program, e))
+}
+
+/// Return an error unless the output is from a successful exit.
+pub fn check_output(program: &str, output: Output) -> Result<Output, ExternalCommandError> {
+    if output.status.success() {
+        Ok(output)
+    } else {
+        Err(ExternalCommandError::Failed {
+            program: program.to_string(),
+            status: output.status,
+            stderr_tail: stderr_tail(&output.stderr, STDERR_TAIL_LINES),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_stderr_tail(){
+        assert_eq!("c\nd", stderr_tail(b"a\nb\nc\nd\n", 2));
+        assert_eq!("a", stderr_tail(b"a\n", 2));
+        assert_eq!("", stderr_tail(b"", 2));
+    }
+
+    #[test]
+    fn test_run_command(){
+        let output = run_command(Command::new("sh").arg("-c").arg("echo out"), "sh").unwrap();
+        assert_eq!(b"out\n".to_vec(), output.stdout);
+
+        match run_command(Command::new("sh").arg("-c").arg("echo bad >&2; exit 3"), "sh") {
+            Err(ExternalCommandError::Failed { program, status, stderr_tail }) => {
+                assert_eq!("sh", program);
+                assert_eq!(Some(3), status.code());
+                assert_eq!("bad", stderr_tail);
+            },
+            _ => panic!("Expected command to fail")
+        }
+
+        match run_command(&mut Command::new("kmer_indexer_no_such_program"), "nothing") {
+            Err(ExternalCommandError::NotFound { program }) => assert_eq!("nothing", program),
+            _ => panic!("Expected program not to be found")
+        }
+    }
+}
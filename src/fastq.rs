@@ -0,0 +1,335 @@
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+use std::str;
+
+use compression;
+use {invalid_data, trim_line_ending, SequenceRead, SequenceRecord, SequenceRecords};
+
+/// A FASTQ record.
+#[derive(Default, Clone, Debug)]
+pub struct Record {
+    id: String,
+    desc: Option<String>,
+    seq: Vec<u8>,
+    qual: Vec<u8>,
+}
+
+impl Record {
+    /// Create a new instance.
+    pub fn new() -> Self {
+        Record {
+            id: String::new(),
+            desc: None,
+            seq: Vec::new(),
+            qual: Vec::new(),
+        }
+    }
+
+    /// Create a new FASTQ record from given attributes.
+    pub fn with_attrs(id: &str, desc: Option<&str>, seq: &[u8], qual: &[u8]) -> Self {
+        Record {
+            id: id.to_owned(),
+            desc: desc.map(|d| d.to_owned()),
+            seq: seq.to_owned(),
+            qual: qual.to_owned(),
+        }
+    }
+
+    /// Check if record is empty.
+    pub fn is_empty(&self) -> bool {
+        self.id.is_empty() && self.desc.is_none() && self.seq.is_empty() && self.qual.is_empty()
+    }
+
+    /// Check validity of FASTQ record.
+    pub fn check(&self) -> Result<(), &str> {
+        if self.id().is_empty() {
+            return Err("Expecting id for FastQ record.");
+        }
+        if !self.seq.is_ascii() {
+            return Err("Non-ascii character found in sequence.");
+        }
+        if !self.qual.is_ascii() {
+            return Err("Non-ascii character found in qualities.");
+        }
+        if self.seq.len() != self.qual.len() {
+            return Err("Unequal length of sequence and qualities.");
+        }
+
+        Ok(())
+    }
+
+    /// Return the id of the record.
+    pub fn id(&self) -> &str {
+        self.id.as_ref()
+    }
+
+    /// Return descriptions if present.
+    pub fn desc(&self) -> Option<&str> {
+        self.desc.as_ref().map(|d| d.as_ref())
+    }
+
+    /// Return the sequence of the record, without line breaks.
+    pub fn seq(&self) -> &[u8] {
+        &self.seq
+    }
+
+    /// Return the base qualities of the record, without line breaks.
+    pub fn qual(&self) -> &[u8] {
+        &self.qual
+    }
+
+    /// Clear the record.
+    fn clear(&mut self) {
+        self.id.clear();
+        self.desc = None;
+        self.seq.clear();
+        self.qual.clear();
+    }
+}
+
+impl SequenceRecord for Record {
+    fn id(&self) -> &str {
+        Record::id(self)
+    }
+
+    fn desc(&self) -> Option<&str> {
+        Record::desc(self)
+    }
+
+    fn seq(&self) -> &[u8] {
+        Record::seq(self)
+    }
+
+    fn qual(&self) -> Option<&[u8]> {
+        Some(Record::qual(self))
+    }
+
+    fn is_empty(&self) -> bool {
+        Record::is_empty(self)
+    }
+}
+
+/// A FASTQ reader. Sequences and qualities may be wrapped over multiple
+/// lines.
+#[derive(Debug)]
+pub struct Reader<R: io::Read> {
+    reader: io::BufReader<R>,
+    line: Vec<u8>,
+}
+
+impl Reader<Box<dyn io::Read + Send>> {
+    /// Read FASTQ from given file path, transparently decompressing gzip,
+    /// bgzip, bzip2 or xz input.
+    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        compression::open_file(path).map(Reader::new)
+    }
+}
+
+impl<R: io::Read> Reader<R> {
+    /// Create a new FASTQ reader given an instance of `io::Read`.
+    pub fn new(reader: R) -> Self {
+        Reader {
+            reader: io::BufReader::new(reader),
+            line: Vec::new(),
+        }
+    }
+
+    /// Read next FASTQ record into the given `Record`. An empty record is
+    /// returned at the end of the input.
+    pub fn read(&mut self, record: &mut Record) -> io::Result<()> {
+        record.clear();
+        self.line.clear();
+        loop {
+            if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+                return Ok(());
+            }
+            if !trim_line_ending(&self.line).is_empty() {
+                break;
+            }
+            self.line.clear();
+        }
+
+        if !self.line.starts_with(b"@") {
+            return Err(invalid_data("Expected @ at record start."));
+        }
+        {
+            let header = match str::from_utf8(trim_line_ending(&self.line[1..])) {
+                Ok(header) => header,
+                Err(_) => return Err(invalid_data("Non-UTF8 characters found in FastQ header."))
+            };
+            let mut fields = header.splitn(2, ' ');
+            record.id.push_str(fields.next().unwrap());
+            record.desc = fields.next().map(|s| s.to_owned());
+        }
+
+        loop {
+            self.line.clear();
+            if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+                return Err(invalid_data("Incomplete record. Expected + line after sequence."));
+            }
+            if self.line.starts_with(b"+") {
+                break;
+            }
+            record.seq.extend_from_slice(trim_line_ending(&self.line));
+        }
+
+        while record.qual.len() < record.seq.len() {
+            self.line.clear();
+            if self.reader.read_until(b'\n', &mut self.line)? == 0 {
+                break;
+            }
+            record.qual.extend_from_slice(trim_line_ending(&self.line));
+        }
+        if record.qual.len() != record.seq.len() {
+            return Err(invalid_data(format!(
+                "Unequal length of sequence and qualities in record {}.", record.id)));
+        }
+
+        Ok(())
+    }
+
+    /// Return an iterator over the records of this FASTQ file.
+    pub fn records(self) -> SequenceRecords<Self> {
+        self.sequence_records()
+    }
+}
+
+impl<R: io::Read> SequenceRead for Reader<R> {
+    type Record = Record;
+
+    fn read(&mut self, record: &mut Record) -> io::Result<()> {
+        Reader::read(self, record)
+    }
+}
+
+/// Strip a trailing /1 or /2 mate suffix from a read name.
+pub fn read_name_without_mate_suffix(id: &str) -> &str {
+    if id.ends_with("/1") || id.ends_with("/2") {
+        &id[..id.len()-2]
+    } else {
+        id
+    }
+}
+
+/// An iterator over the records of two files of paired reads in lockstep,
+/// e.g. R1 and R2 FASTQ files. Records are required to have matching names
+/// once any /1 and /2 suffixes are removed.
+pub struct PairedRecords<S1: SequenceRead, S2: SequenceRead> {
+    forward: SequenceRecords<S1>,
+    reverse: SequenceRecords<S2>,
+    error_has_occured: bool,
+}
+
+impl<S1: SequenceRead, S2: SequenceRead> PairedRecords<S1, S2> {
+    /// Pair the records of the given forward and reverse readers.
+    pub fn new(forward: S1, reverse: S2) -> Self {
+        PairedRecords {
+            forward: forward.sequence_records(),
+            reverse: reverse.sequence_records(),
+            error_has_occured: false,
+        }
+    }
+
+    fn next_pair(&mut self) -> Option<io::Result<(S1::Record, S2::Record)>> {
+        match (self.forward.next(), self.reverse.next()) {
+            (None, None) => None,
+            (Some(Err(e)), _) | (_, Some(Err(e))) => Some(Err(e)),
+            (Some(Ok(_)), None) | (None, Some(Ok(_))) => Some(Err(invalid_data(
+                "Paired files contain differing numbers of records."))),
+            (Some(Ok(forward)), Some(Ok(reverse))) => {
+                if read_name_without_mate_suffix(forward.id()) !=
+                    read_name_without_mate_suffix(reverse.id()) {
+                    Some(Err(invalid_data(format!(
+                        "Paired records have mismatched names: {} and {}",
+                        forward.id(), reverse.id()))))
+                } else {
+                    Some(Ok((forward, reverse)))
+                }
+            }
+        }
+    }
+}
+
+impl<S1: SequenceRead, S2: SequenceRead> Iterator for PairedRecords<S1, S2> {
+    type Item = io::Result<(S1::Record, S2::Record)>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.error_has_occured {
+            return None
+        }
+        let pair = self.next_pair();
+        if let Some(Err(_)) = pair {
+            self.error_has_occured = true;
+        }
+        pair
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_read_multiline_fastq(){
+        const FASTQ: &[u8] = b"@read1 desc
+ACGT
+AC
++
+IIII
+II
+
+@read2
+GG
++read2
+@@
+";
+        let records: Vec<Record> = Reader::new(FASTQ).records().map(|r| r.unwrap()).collect();
+        assert_eq!(2, records.len());
+        assert_eq!("read1", records[0].id());
+        assert_eq!(Some("desc"), records[0].desc());
+        assert_eq!(b"ACGTAC", records[0].seq());
+        assert_eq!(b"IIIIII", records[0].qual());
+        assert_eq!("read2", records[1].id());
+        assert_eq!(b"@@", records[1].qual());
+        assert!(records[1].check().is_ok());
+    }
+
+    #[test]
+    fn test_read_truncated_fastq(){
+        const FASTQ: &[u8] = b"@read1\nACGT\n+\nII\n";
+        let mut records = Reader::new(FASTQ).records();
+        assert!(records.next().unwrap().is_err());
+        assert!(records.next().is_none());
+    }
+
+    #[test]
+    fn test_read_fastq_file(){
+        let records: Vec<Record> = Reader::from_file("tests/data/reads_for_seq1.fq").unwrap()
+            .records().map(|r| r.unwrap()).collect();
+        assert_eq!(12, records.len());
+        assert_eq!("1", records[0].id());
+        assert_eq!(Some("1"), records[0].desc());
+        assert_eq!(100, records[0].seq().len());
+    }
+
+    #[test]
+    fn test_paired_records(){
+        let pairs: Vec<(Record, Record)> = PairedRecords::new(
+            Reader::from_file("tests/data/reads_for_seq1_1.fq").unwrap(),
+            Reader::from_file("tests/data/reads_for_seq1_2.fq").unwrap())
+            .map(|r| r.unwrap()).collect();
+        assert_eq!(6, pairs.len());
+        assert_eq!("1/1", pairs[0].0.id());
+        assert_eq!("1/2", pairs[0].1.id());
+    }
+
+    #[test]
+    fn test_paired_records_mismatch(){
+        let mut pairs = PairedRecords::new(
+            Reader::new(&b"@a/1\nA\n+\nI\n"[..]),
+            Reader::new(&b"@b/2\nA\n+\nI\n"[..]));
+        assert!(pairs.next().unwrap().is_err());
+        assert!(pairs.next().is_none());
+    }
+}
@@ -1,17 +1,109 @@
 
 use std;
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::collections::hash_map::Entry;
+
+use kmer::{Kmer, Kmers, WindowCounts};
+use kmer_table::KmerTableWriter;
+use std::io;
+use std::io::prelude::*;
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::thread;
 use std::str;
 
 #[derive(Debug)]
 pub struct GenomesAndContigs {
     pub genomes: Vec<String>,
-    pub contig_to_genome: Vec<String>
+    pub contigs: Vec<String>,
+    pub sequences: Vec<Vec<u8>>,
+    contig_to_genome: HashMap<String, usize>,
+    /// Contig names used in more than one genome, which are left out of
+    /// `contig_to_genome`.
+    ambiguous_contigs: HashSet<String>,
+    contig_genome_indices: Vec<usize>,
+    genome_to_contigs: Vec<Vec<usize>>,
+}
+
+/// Counts of each k-mer in each genome, with k-mers packed into integers.
+pub struct KmerMap<T: Kmer>{
+    pub kmer_size: usize,
+    /// Whether each k-mer was counted together with its reverse complement.
+    pub canonical: bool,
+    /// Names of the genomes, in the order of the counts of each k-mer.
+    pub genomes: Vec<String>,
+    pub kmers: HashMap<T, Vec<u32>>,
+    /// Number of windows counted and skipped in each genome.
+    pub window_counts: Vec<WindowCounts>,
 }
 
-pub struct KmerMap{
-    pub kmers: HashMap<String, Vec<u32>>
+impl<T: Kmer> KmerMap<T> {
+    pub fn new(kmer_size: usize, canonical: bool, genomes: Vec<String>) -> KmerMap<T> {
+        let num_genomes = genomes.len();
+        KmerMap {
+            kmer_size,
+            canonical,
+            genomes,
+            kmers: HashMap::new(),
+            window_counts: vec![WindowCounts::default(); num_genomes],
+        }
+    }
+
+    /// Return the bases of an encoded k-mer.
+    pub fn decode(&self, kmer: T) -> String {
+        kmer.decode(self.kmer_size)
+    }
+
+    /// Return the k-mers in ascending order of their encoding, which is
+    /// lexicographic order of their bases.
+    pub fn sorted_kmers(&self) -> Vec<T> {
+        let mut kmers: Vec<T> = self.kmers.keys().cloned().collect();
+        kmers.sort();
+        kmers
+    }
+
+    /// Write the table of counts, one row per k-mer in sorted order.
+    pub fn write_table(&self, writer: &mut dyn KmerTableWriter) -> io::Result<()> {
+        writer.write_header(&self.genomes, self.kmer_size, self.canonical)?;
+        for kmer in self.sorted_kmers() {
+            writer.write_row(kmer.to_u128(), &self.kmers[&kmer])?;
+        }
+        writer.finish()
+    }
+
+    /// Return, for each genome, the k-mers found in it and in no more than
+    /// max_other_genomes other genomes, in sorted order and with their count
+    /// in that genome. With max_other_genomes of 0 these are the k-mers
+    /// unique to each genome.
+    pub fn unique_kmers(&self, max_other_genomes: usize) -> Vec<Vec<(T, u32)>> {
+        let mut unique = vec![vec!(); self.genomes.len()];
+        for kmer in self.sorted_kmers() {
+            let counts = &self.kmers[&kmer];
+            let num_genomes = counts.iter().filter(|c| **c > 0).count();
+            if num_genomes == 0 || num_genomes - 1 > max_other_genomes {
+                continue
+            }
+            for (i, count) in counts.iter().enumerate() {
+                if *count > 0 {
+                    unique[i].push((kmer, *count));
+                }
+            }
+        }
+        unique
+    }
+
+    /// Write the k-mers returned by `unique_kmers` as TSV with a row for each
+    /// genome and k-mer.
+    pub fn write_unique_kmers<W: io::Write>(&self, max_other_genomes: usize, writer: W) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(writer);
+        writeln!(writer, "genome\tkmer\tcount")?;
+        for (genome, kmers) in self.genomes.iter().zip(self.unique_kmers(max_other_genomes)) {
+            for (kmer, count) in kmers {
+                writeln!(writer, "{}\t{}\t{}", genome, self.decode(kmer), count)?;
+            }
+        }
+        writer.flush()
+    }
 }
 
 
@@ -19,60 +111,211 @@ impl GenomesAndContigs {
     pub fn new() -> GenomesAndContigs {
         GenomesAndContigs {
             genomes: vec!(),
-            contig_to_genome: vec!(),
+            contigs: vec!(),
+            sequences: vec!(),
+            contig_to_genome: HashMap::new(),
+            ambiguous_contigs: HashSet::new(),
+            contig_genome_indices: vec!(),
+            genome_to_contigs: vec!(),
         }
     }
 
+    /// Add a new genome, returning its index. Contigs inserted after this
+    /// call are assigned to it.
     pub fn establish_genome(&mut self, genome_name: String) -> usize {
         let index = self.genomes.len();
         self.genomes.push(genome_name);
+        self.genome_to_contigs.push(vec!());
         return index
     }
 
-    pub fn establish_kmers(self, kmer_size: usize) {
-        let mut kmer_map = KmerMap{
-                    kmers: HashMap::new()
-                };
-        for (i, genome) in self.contig_to_genome.iter().enumerate(){
-            let mut start = 0;
-            let mut stop = start + kmer_size;
-            let mut full_gen = Vec::new();
-            let mut gen = genome.lines();
-            for line in gen{
-                full_gen.push(line);
+    /// Count the k-mers of each genome. k-mers must fit in T, so up to 32
+    /// bases can be counted as u64 and up to 64 as u128. If canonical is
+    /// set, each k-mer is counted together with its reverse complement.
+    /// k-mers are taken from within each contig, skipping any containing
+    /// non-ACGT bases, and the number of windows counted and skipped in each
+    /// genome is recorded in the map.
+    pub fn establish_kmers<T: Kmer>(self, kmer_size: usize, canonical: bool) -> KmerMap<T> {
+        let num_genomes = self.genomes.len();
+        let mut kmer_map = KmerMap::<T>::new(kmer_size, canonical, self.genomes.clone());
+        for i in 0..num_genomes {
+            let (counts, window_counts) = self.count_genome_kmers::<T>(i, kmer_size, canonical);
+            for (kmer, count) in counts {
+                kmer_map.kmers.entry(kmer).or_insert(vec![0; num_genomes])[i] = count;
+            }
+            kmer_map.window_counts[i] = window_counts;
+        }
+        kmer_map
+    }
+
+    /// Count k-mers as `establish_kmers` does, with genomes counted in
+    /// parallel by the given number of threads. The counts of each genome
+    /// are merged into shards of the map, each behind its own lock, and the
+    /// resulting map is the same as that counted serially.
+    pub fn establish_kmers_in_threads<T: Kmer>(self, kmer_size: usize, canonical: bool,
+                                               threads: usize) -> KmerMap<T> {
+        if threads <= 1 {
+            return self.establish_kmers(kmer_size, canonical)
+        }
+        let num_genomes = self.genomes.len();
+        let mut kmer_map = KmerMap::<T>::new(kmer_size, canonical, self.genomes.clone());
+        let shards: Vec<Mutex<HashMap<T, Vec<u32>>>> =
+            (0..threads).map(|_| Mutex::new(HashMap::new())).collect();
+        let next_genome = AtomicUsize::new(0);
+
+        thread::scope(|scope| {
+            let workers: Vec<_> = (0..threads).map(|_| {
+                scope.spawn(|| {
+                    let mut window_counts = vec!();
+                    loop {
+                        let i = next_genome.fetch_add(1, Ordering::SeqCst);
+                        if i >= num_genomes {
+                            break
+                        }
+                        let (counts, genome_window_counts) =
+                            self.count_genome_kmers::<T>(i, kmer_size, canonical);
+                        let mut sharded = vec![vec!(); shards.len()];
+                        for (kmer, count) in counts {
+                            sharded[(kmer.to_u128() % shards.len() as u128) as usize].push((kmer, count));
+                        }
+                        for (shard, kmers) in shards.iter().zip(sharded) {
+                            let mut shard = shard.lock().unwrap();
+                            for (kmer, count) in kmers {
+                                shard.entry(kmer).or_insert(vec![0; num_genomes])[i] = count;
+                            }
+                        }
+                        window_counts.push((i, genome_window_counts));
+                    }
+                    window_counts
+                })
+            }).collect();
+
+            for worker in workers {
+                for (i, window_counts) in worker.join().expect("k-mer counting thread panicked") {
+                    kmer_map.window_counts[i] = window_counts;
+                }
             }
-            let mut joined_genome = full_gen.join("").to_uppercase();
-            while stop <= joined_genome.len() as usize{
-                let kmer = &joined_genome.as_bytes()[start..stop];
-                let value_vec = kmer_map.kmers.entry(str::from_utf8(&kmer)
-                                                        .unwrap().to_string())
-                                                        .or_insert(vec![0; self.genomes.len()]);
-                value_vec[i] += 1;
-//                if self.kmers.contains_key(str::from_utf8(kmer).unwrap()){
-//                    *self.kmers[i].get_mut(str::from_utf8(kmer).unwrap()).unwrap() += 1;
-//                }else{
-//                    self.kmers[i].insert(str::from_utf8(kmer).unwrap().to_string(), 1);
-//                }
-                start += 1;
-                stop += 1;
+        });
+        for shard in shards {
+            kmer_map.kmers.extend(shard.into_inner().unwrap());
+        }
+        kmer_map
+    }
+
+    /// Count the k-mers of one genome, returning the count of each and the
+    /// number of windows counted and skipped.
+    pub fn count_genome_kmers<T: Kmer>(&self, genome_index: usize, kmer_size: usize,
+                                   canonical: bool) -> (HashMap<T, u32>, WindowCounts) {
+        let mut counts = HashMap::new();
+        let mut window_counts = WindowCounts::default();
+        let contig_indices = &self.genome_to_contigs[genome_index];
+        let mut contig_lengths = Vec::with_capacity(contig_indices.len());
+        for contig_index in contig_indices{
+            let sequence = &self.sequences[*contig_index];
+            contig_lengths.push(sequence.len());
+            let mut kmers = Kmers::<T>::new(sequence, kmer_size, canonical);
+            for kmer in kmers.by_ref(){
+                *counts.entry(kmer).or_insert(0) += 1;
             }
+            window_counts.add(kmers.window_counts());
         }
-        print!("K-Mer ");
-        for genome in self.genomes{
-            print!("\t{} ", genome.split("/").collect::<Vec<_>>().last().unwrap());
+        window_counts.add_contig_boundaries(&contig_lengths, kmer_size);
+        (counts, window_counts)
+    }
+
+    /// Move the genomes and contigs of another collection to the end of this
+    /// one, keeping their order.
+    pub fn append(&mut self, other: GenomesAndContigs) {
+        let GenomesAndContigs { genomes, contigs, sequences, genome_to_contigs, .. } = other;
+        let mut contigs: Vec<Option<(String, Vec<u8>)>> = contigs.into_iter()
+            .zip(sequences).map(Some).collect();
+        for (genome, contig_indices) in genomes.into_iter().zip(genome_to_contigs) {
+            self.establish_genome(genome);
+            for contig_index in contig_indices {
+                let (name, sequence) = contigs[contig_index].take().unwrap();
+                self.insert_with_sequence(name, sequence);
+            }
         }
-        print!("\n");
-        for (key, value) in kmer_map.kmers.drain(){
-            print!("{} ", key);
-            for v in value{
-                print!("\t{} ", v);
+    }
+
+    /// Add a contig to the most recently established genome, returning the
+    /// index of the contig.
+    pub fn insert(&mut self, contig_name: String) -> usize {
+        self.insert_with_sequence(contig_name, Vec::new())
+    }
+
+    /// Add a contig and its sequence to the most recently established genome,
+    /// returning the index of the contig. A contig name used in more than
+    /// one genome, such as an assembler's default `contig_1`, can no longer
+    /// be looked up by name, so that no lookup returns the wrong genome.
+    /// Contigs are still reachable by index.
+    pub fn insert_with_sequence(&mut self, contig_name: String, sequence: Vec<u8>) -> usize {
+        let genome_index = match self.genomes.len() {
+            0 => panic!("Cannot insert contig {} before establishing a genome", contig_name),
+            n => n - 1
+        };
+        let contig_index = self.contigs.len();
+        if !self.ambiguous_contigs.contains(&contig_name) {
+            match self.contig_to_genome.insert(contig_name.clone(), genome_index) {
+                Some(previous) if previous != genome_index => {
+                    self.contig_to_genome.remove(&contig_name);
+                    self.ambiguous_contigs.insert(contig_name.clone());
+                },
+                _ => {}
             }
-            print!("\n")
         }
+        self.contigs.push(contig_name);
+        self.sequences.push(sequence);
+        self.contig_genome_indices.push(genome_index);
+        self.genome_to_contigs[genome_index].push(contig_index);
+        contig_index
+    }
+
+    /// Return the index of the genome with the given name.
+    pub fn genome_index(&self, genome_name: &String) -> Option<usize> {
+        find_first(&self.genomes, genome_name.to_string()).ok()
+    }
+
+    /// Return the index of the genome the named contig belongs to, or None if
+    /// no contig or contigs of more than one genome have that name.
+    pub fn genome_index_of_contig(&self, contig_name: &String) -> Option<usize> {
+        self.contig_to_genome.get(contig_name).cloned()
+    }
+
+    /// Return the name of the genome the named contig belongs to, as
+    /// `genome_index_of_contig` does.
+    pub fn genome_of_contig(&self, contig_name: &String) -> Option<&String> {
+        match self.contig_to_genome.get(contig_name) {
+            Some(genome_index) => self.genomes.get(*genome_index),
+            None => None
+        }
+    }
+
+    /// Return the index of the genome the contig at the given index belongs
+    /// to.
+    pub fn genome_index_of_contig_index(&self, contig_index: usize) -> Option<usize> {
+        self.contig_genome_indices.get(contig_index).cloned()
+    }
+
+    /// Return the indices of the contigs belonging to the genome at the given
+    /// index, in insertion order.
+    pub fn contig_indices_of_genome(&self, genome_index: usize) -> Option<&Vec<usize>> {
+        self.genome_to_contigs.get(genome_index)
+    }
+
+    /// Return the names of the contigs belonging to the named genome, in
+    /// insertion order.
+    pub fn contigs_of_genome(&self, genome_name: &String) -> Option<Vec<&String>> {
+        self.genome_index(genome_name).map(|genome_index| {
+            self.genome_to_contigs[genome_index].iter()
+                .map(|contig_index| &self.contigs[*contig_index])
+                .collect()
+        })
     }
 
-    pub fn insert(&mut self, contig_name: String) {
-        self.contig_to_genome.push(contig_name);
+    /// Number of contigs across all genomes.
+    pub fn num_contigs(&self) -> usize {
+        self.contigs.len()
     }
 
 //    pub fn get_kmers(&mut self){
@@ -0,0 +1,331 @@
+use std::fmt::Debug;
+use std::hash::Hash;
+use std::io;
+
+const CODE_BASES: [u8; 4] = [b'A', b'C', b'G', b'T'];
+
+/// Return the 2-bit code of a base, or None if it is not one of ACGT. Codes
+/// are A=0, C=1, G=2 and T=3, so that the complement of a base is 3 minus
+/// its code and the numeric order of encoded k-mers is their lexicographic
+/// order.
+#[inline]
+pub fn base_code(base: u8) -> Option<u8> {
+    match base {
+        b'A' | b'a' => Some(0),
+        b'C' | b'c' => Some(1),
+        b'G' | b'g' => Some(2),
+        b'T' | b't' => Some(3),
+        _ => None
+    }
+}
+
+/// An integer holding a k-mer packed at 2 bits per base, with the last base
+/// in the lowest bits.
+pub trait Kmer: Copy + Eq + Ord + Hash + Debug + Send + Sync + 'static {
+    /// Longest k-mer which fits.
+    const MAX_K: usize;
+
+    /// Append a base to the right of a k-mer of length k, dropping its first
+    /// base.
+    fn push_back(self, code: u8, k: usize) -> Self;
+
+    /// Prepend a base to the left of a k-mer of length k, dropping its last
+    /// base. Used to roll the reverse complement along with the forward
+    /// strand.
+    fn push_front(self, code: u8, k: usize) -> Self;
+
+    /// Return the 2-bit code of the base at position i.
+    fn base_at(self, i: usize, k: usize) -> u8;
+
+    fn zero() -> Self;
+
+    fn to_u128(self) -> u128;
+
+    fn from_u128(value: u128) -> Self;
+
+    /// Encode a k-mer, returning None if it contains non-ACGT bases or is
+    /// too long.
+    fn encode(sequence: &[u8]) -> Option<Self> {
+        if sequence.len() > Self::MAX_K {
+            return None
+        }
+        let mut kmer = Self::zero();
+        for base in sequence {
+            kmer = kmer.push_back(base_code(*base)?, sequence.len());
+        }
+        Some(kmer)
+    }
+
+    /// Decode a k-mer of length k into its bases.
+    fn decode(self, k: usize) -> String {
+        (0..k).map(|i| CODE_BASES[self.base_at(i, k) as usize] as char).collect()
+    }
+
+    /// Return the reverse complement of a k-mer of length k.
+    fn reverse_complement(self, k: usize) -> Self {
+        let mut reverse = Self::zero();
+        for i in 0..k {
+            reverse = reverse.push_front(3 - self.base_at(i, k), k);
+        }
+        reverse
+    }
+
+    /// Return the lesser of the k-mer and its reverse complement.
+    fn canonical(self, k: usize) -> Self {
+        let reverse = self.reverse_complement(k);
+        if reverse < self { reverse } else { self }
+    }
+}
+
+macro_rules! impl_kmer {
+    ($t:ty, $bits:expr) => {
+        impl Kmer for $t {
+            const MAX_K: usize = $bits / 2;
+
+            #[inline]
+            fn push_back(self, code: u8, k: usize) -> $t {
+                let shifted = if k == Self::MAX_K {
+                    self << 2
+                } else {
+                    (self << 2) & (((1 as $t) << (2 * k)) - 1)
+                };
+                shifted | code as $t
+            }
+
+            #[inline]
+            fn push_front(self, code: u8, k: usize) -> $t {
+                (self >> 2) | ((code as $t) << (2 * (k - 1)))
+            }
+
+            #[inline]
+            fn base_at(self, i: usize, k: usize) -> u8 {
+                ((self >> (2 * (k - 1 - i))) & 3) as u8
+            }
+
+            fn zero() -> $t {
+                0
+            }
+
+            fn to_u128(self) -> u128 {
+                self as u128
+            }
+
+            fn from_u128(value: u128) -> $t {
+                value as $t
+            }
+        }
+    }
+}
+
+impl_kmer!(u64, 64);
+impl_kmer!(u128, 128);
+
+/// Tally of the k-length windows of a genome: how many were counted as
+/// k-mers, and how many were skipped and why.
+#[derive(Debug, Default, Clone, PartialEq)]
+pub struct WindowCounts {
+    pub counted: u64,
+    /// Windows containing an N.
+    pub skipped_n: u64,
+    /// Windows containing other non-ACGT characters, such as IUPAC
+    /// ambiguity codes, but no N.
+    pub skipped_other_ambiguous: u64,
+    /// Windows which would span two contigs if the contigs were joined.
+    pub skipped_contig_boundary: u64,
+}
+
+impl WindowCounts {
+    /// Add the counts of another tally to this one.
+    pub fn add(&mut self, other: &WindowCounts) {
+        self.counted += other.counted;
+        self.skipped_n += other.skipped_n;
+        self.skipped_other_ambiguous += other.skipped_other_ambiguous;
+        self.skipped_contig_boundary += other.skipped_contig_boundary;
+    }
+
+    /// Record the windows lost by not joining contigs of the given lengths.
+    pub fn add_contig_boundaries(&mut self, contig_lengths: &[usize], k: usize) {
+        let windows = |length: usize| if length >= k { (length - k + 1) as u64 } else { 0 };
+        let joined = windows(contig_lengths.iter().sum());
+        let separate: u64 = contig_lengths.iter().map(|l| windows(*l)).sum();
+        self.skipped_contig_boundary += joined - separate;
+    }
+}
+
+/// Write the window tallies of each genome as TSV.
+pub fn write_window_counts<W: io::Write>(
+    genomes: &[String], window_counts: &[WindowCounts], writer: &mut W) -> io::Result<()> {
+    writeln!(writer, "genome\tcounted\tskipped_n\tskipped_other_ambiguous\tskipped_contig_boundary")?;
+    for (genome, counts) in genomes.iter().zip(window_counts.iter()) {
+        writeln!(writer, "{}\t{}\t{}\t{}\t{}", genome, counts.counted, counts.skipped_n,
+                 counts.skipped_other_ambiguous, counts.skipped_contig_boundary)?;
+    }
+    Ok(())
+}
+
+/// An iterator over the encoded k-mers of a sequence. Windows containing
+/// bases other than ACGT are skipped, and tallied in `window_counts`.
+pub struct Kmers<'a, T: Kmer> {
+    sequence: &'a [u8],
+    position: usize,
+    k: usize,
+    canonical: bool,
+    forward: T,
+    reverse: T,
+    valid_bases: usize,
+    last_n: Option<usize>,
+    last_other_ambiguous: Option<usize>,
+    window_counts: WindowCounts,
+}
+
+impl<'a, T: Kmer> Kmers<'a, T> {
+    /// Iterate over the k-mers of a sequence, yielding the lesser of each
+    /// k-mer and its reverse complement if canonical is set, otherwise the
+    /// forward strand k-mer.
+    pub fn new(sequence: &'a [u8], k: usize, canonical: bool) -> Kmers<'a, T> {
+        assert!(k > 0 && k <= T::MAX_K, "k-mer size {} out of range 1-{}", k, T::MAX_K);
+        Kmers {
+            sequence,
+            position: 0,
+            k,
+            canonical,
+            forward: T::zero(),
+            reverse: T::zero(),
+            valid_bases: 0,
+            last_n: None,
+            last_other_ambiguous: None,
+            window_counts: WindowCounts::default(),
+        }
+    }
+
+    /// Tally of the windows iterated over so far.
+    pub fn window_counts(&self) -> &WindowCounts {
+        &self.window_counts
+    }
+
+    fn contains_since(last: Option<usize>, window_start: usize) -> bool {
+        match last {
+            Some(position) => position >= window_start,
+            None => false
+        }
+    }
+}
+
+impl<'a, T: Kmer> Iterator for Kmers<'a, T> {
+    type Item = T;
+
+    fn next(&mut self) -> Option<T> {
+        while self.position < self.sequence.len() {
+            let i = self.position;
+            let base = self.sequence[i];
+            self.position += 1;
+            match base_code(base) {
+                Some(code) => {
+                    self.forward = self.forward.push_back(code, self.k);
+                    self.reverse = self.reverse.push_front(3 - code, self.k);
+                    self.valid_bases += 1;
+                    if self.valid_bases >= self.k {
+                        self.window_counts.counted += 1;
+                        return Some(if self.canonical && self.reverse < self.forward {
+                            self.reverse
+                        } else {
+                            self.forward
+                        })
+                    }
+                },
+                None => {
+                    self.valid_bases = 0;
+                    if base == b'N' || base == b'n' {
+                        self.last_n = Some(i);
+                    } else {
+                        self.last_other_ambiguous = Some(i);
+                    }
+                }
+            }
+            if i + 1 >= self.k {
+                let window_start = i + 1 - self.k;
+                if Self::contains_since(self.last_n, window_start) {
+                    self.window_counts.skipped_n += 1;
+                } else if Self::contains_since(self.last_other_ambiguous, window_start) {
+                    self.window_counts.skipped_other_ambiguous += 1;
+                }
+            }
+        }
+        None
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_encode_decode(){
+        let kmer = u64::encode(b"ACGTTGCA").unwrap();
+        assert_eq!(0b0001101111100100, kmer);
+        assert_eq!("ACGTTGCA", kmer.decode(8));
+        assert_eq!(Some(kmer), u64::encode(b"acgttgca"));
+        assert_eq!(None, u64::encode(b"ACGN"));
+
+        let long = b"ACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGTACGT";
+        assert_eq!(None, u64::encode(&long[..33]));
+        assert_eq!(String::from_utf8(long[..32].to_vec()).unwrap(), u64::encode(&long[..32]).unwrap().decode(32));
+        assert_eq!(String::from_utf8(long.to_vec()).unwrap(), u128::encode(long).unwrap().decode(64));
+    }
+
+    #[test]
+    fn test_canonical(){
+        let kmer = u64::encode(b"CGT").unwrap();
+        assert_eq!(u64::encode(b"ACG").unwrap(), kmer.reverse_complement(3));
+        assert_eq!(u64::encode(b"ACG").unwrap(), kmer.canonical(3));
+        assert_eq!(u64::encode(b"ACG").unwrap(), u64::encode(b"ACG").unwrap().canonical(3));
+    }
+
+    #[test]
+    fn test_kmers(){
+        let forward: Vec<String> = Kmers::<u64>::new(b"ACGTN", 3, false)
+            .map(|k| k.decode(3)).collect();
+        assert_eq!(vec!["ACG", "CGT"], forward);
+        let canonical: Vec<String> = Kmers::<u64>::new(b"ACGTNAAAC", 3, true)
+            .map(|k| k.decode(3)).collect();
+        assert_eq!(vec!["ACG", "ACG", "AAA", "AAC"], canonical);
+        assert_eq!(0, Kmers::<u64>::new(b"AC", 3, true).count());
+    }
+
+    #[test]
+    fn test_window_counts(){
+        let mut kmers = Kmers::<u64>::new(b"ACGTNAARAC", 3, true);
+        assert_eq!(2, kmers.by_ref().count());
+        assert_eq!(&WindowCounts {
+            counted: 2,
+            skipped_n: 3,
+            skipped_other_ambiguous: 3,
+            skipped_contig_boundary: 0,
+        }, kmers.window_counts());
+
+        let mut counts = WindowCounts::default();
+        counts.add_contig_boundaries(&[10, 10, 1], 4);
+        assert_eq!(4, counts.skipped_contig_boundary);
+        let mut output = Vec::new();
+        write_window_counts(&["g1".to_string()], &[counts], &mut output).unwrap();
+        assert_eq!(
+            "genome\tcounted\tskipped_n\tskipped_other_ambiguous\tskipped_contig_boundary\n\
+             g1\t0\t0\t0\t4\n",
+            String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn test_kmers_match_encoding(){
+        let sequence = b"GATTACAGATTACAGATTACAGATTACAGATTACAGATTACAGGGG";
+        for &k in [5usize, 32].iter() {
+            let rolled: Vec<u64> = Kmers::<u64>::new(sequence, k, true).collect();
+            let encoded: Vec<u64> = sequence.windows(k)
+                .map(|w| u64::encode(w).unwrap().canonical(k)).collect();
+            assert_eq!(encoded, rolled);
+        }
+        let rolled: Vec<u128> = Kmers::<u128>::new(sequence, 40, true).collect();
+        let encoded: Vec<u128> = sequence.windows(40)
+            .map(|w| u128::encode(w).unwrap().canonical(40)).collect();
+        assert_eq!(encoded, rolled);
+    }
+}
@@ -0,0 +1,318 @@
+use std::io;
+use std::io::prelude::*;
+use std::io::SeekFrom;
+
+use invalid_data;
+use kmer::Kmer;
+
+/// First bytes of a binary k-mer table.
+pub const BINARY_MAGIC: &[u8; 4] = b"KMTB";
+/// Version of the binary k-mer table layout written by `BinaryWriter`.
+pub const BINARY_VERSION: u8 = 1;
+
+/// The formats a k-mer table can be written in.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum KmerTableFormat {
+    /// One row per k-mer with a column of counts for each genome.
+    Tsv,
+    /// Matrix Market coordinate format, listing only non-zero counts.
+    MatrixMarket,
+    /// Compact little-endian records, described by `BinaryWriter`.
+    Binary,
+}
+
+impl KmerTableFormat {
+    /// Parse the command line representation of a format.
+    pub fn from_name(name: &str) -> Option<KmerTableFormat> {
+        match name {
+            "tsv" => Some(KmerTableFormat::Tsv),
+            "matrix-market" => Some(KmerTableFormat::MatrixMarket),
+            "binary" => Some(KmerTableFormat::Binary),
+            _ => None
+        }
+    }
+}
+
+/// Writes a table of k-mer counts. The header is written first, then one
+/// row per k-mer, and `finish` must be called once all rows are written.
+/// k-mers are given in their 2-bit packed form, widened to a u128.
+pub trait KmerTableWriter {
+    fn write_header(&mut self, genomes: &[String], kmer_size: usize, canonical: bool) -> io::Result<()>;
+
+    fn write_row(&mut self, kmer: u128, counts: &[u32]) -> io::Result<()>;
+
+    fn finish(&mut self) -> io::Result<()>;
+}
+
+/// Writes a tab-separated table with a `kmer` column followed by a column
+/// of counts for each genome.
+pub struct TsvWriter<W: io::Write> {
+    writer: io::BufWriter<W>,
+    kmer_size: usize,
+}
+
+impl<W: io::Write> TsvWriter<W> {
+    pub fn new(writer: W) -> Self {
+        TsvWriter {
+            writer: io::BufWriter::new(writer),
+            kmer_size: 0,
+        }
+    }
+
+    /// Flush the table and return the underlying writer.
+    pub fn into_inner(self) -> io::Result<W> {
+        self.writer.into_inner().map_err(|e| e.into())
+    }
+}
+
+impl<W: io::Write> KmerTableWriter for TsvWriter<W> {
+    fn write_header(&mut self, genomes: &[String], kmer_size: usize, _canonical: bool) -> io::Result<()> {
+        self.kmer_size = kmer_size;
+        write!(self.writer, "kmer")?;
+        for genome in genomes {
+            write!(self.writer, "\t{}", genome)?;
+        }
+        writeln!(self.writer)
+    }
+
+    fn write_row(&mut self, kmer: u128, counts: &[u32]) -> io::Result<()> {
+        write!(self.writer, "{}", kmer.decode(self.kmer_size))?;
+        for count in counts {
+            write!(self.writer, "\t{}", count)?;
+        }
+        writeln!(self.writer)
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Writes a Matrix Market coordinate matrix with a row per k-mer and a
+/// column per genome, leaving out zero counts. Genome names are written as
+/// comments in the header, and the k-mer of each row is written one per line
+/// to a separate labels writer. Since the header records the number of
+/// non-zero entries, entries are buffered in a temporary file until
+/// `finish`.
+pub struct MatrixMarketWriter<W: io::Write, L: io::Write> {
+    writer: io::BufWriter<W>,
+    labels: io::BufWriter<L>,
+    entries: io::BufWriter<::std::fs::File>,
+    kmer_size: usize,
+    num_genomes: usize,
+    num_rows: usize,
+    num_entries: usize,
+}
+
+impl<W: io::Write, L: io::Write> MatrixMarketWriter<W, L> {
+    pub fn new(writer: W, labels: L) -> io::Result<Self> {
+        Ok(MatrixMarketWriter {
+            writer: io::BufWriter::new(writer),
+            labels: io::BufWriter::new(labels),
+            entries: io::BufWriter::new(::tempfile::tempfile()?),
+            kmer_size: 0,
+            num_genomes: 0,
+            num_rows: 0,
+            num_entries: 0,
+        })
+    }
+}
+
+impl<W: io::Write, L: io::Write> KmerTableWriter for MatrixMarketWriter<W, L> {
+    fn write_header(&mut self, genomes: &[String], kmer_size: usize, canonical: bool) -> io::Result<()> {
+        self.kmer_size = kmer_size;
+        self.num_genomes = genomes.len();
+        writeln!(self.writer, "%%MatrixMarket matrix coordinate integer general")?;
+        writeln!(self.writer, "% kmer_size {}", kmer_size)?;
+        writeln!(self.writer, "% canonical {}", canonical)?;
+        for genome in genomes {
+            writeln!(self.writer, "% genome {}", genome)?;
+        }
+        Ok(())
+    }
+
+    fn write_row(&mut self, kmer: u128, counts: &[u32]) -> io::Result<()> {
+        self.num_rows += 1;
+        writeln!(self.labels, "{}", kmer.decode(self.kmer_size))?;
+        for (column, count) in counts.iter().enumerate() {
+            if *count > 0 {
+                writeln!(self.entries, "{} {} {}", self.num_rows, column + 1, count)?;
+                self.num_entries += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        writeln!(self.writer, "{} {} {}", self.num_rows, self.num_genomes, self.num_entries)?;
+        self.entries.flush()?;
+        let entries = self.entries.get_mut();
+        entries.seek(SeekFrom::Start(0))?;
+        io::copy(entries, &mut self.writer)?;
+        self.labels.flush()?;
+        self.writer.flush()
+    }
+}
+
+/// Writes a binary table. All integers are little-endian. The header is
+/// the magic bytes `KMTB`, a version byte, the k-mer size, a byte which is 1
+/// if k-mers are canonical, the number of bytes used for each k-mer (8 up to
+/// k=32, 16 above), a u32 number of genomes and then each genome name as a
+/// u32 length followed by its UTF-8 bytes. Each row follows as the packed
+/// k-mer and then a u32 count for each genome, until the end of the file.
+pub struct BinaryWriter<W: io::Write> {
+    writer: io::BufWriter<W>,
+    kmer_bytes: usize,
+}
+
+impl<W: io::Write> BinaryWriter<W> {
+    pub fn new(writer: W) -> Self {
+        BinaryWriter {
+            writer: io::BufWriter::new(writer),
+            kmer_bytes: 16,
+        }
+    }
+}
+
+/// Number of bytes used for each k-mer of the given size in the binary
+/// format.
+pub fn binary_kmer_bytes(kmer_size: usize) -> usize {
+    if kmer_size <= u64::MAX_K { 8 } else { 16 }
+}
+
+impl<W: io::Write> KmerTableWriter for BinaryWriter<W> {
+    fn write_header(&mut self, genomes: &[String], kmer_size: usize, canonical: bool) -> io::Result<()> {
+        self.kmer_bytes = binary_kmer_bytes(kmer_size);
+        self.writer.write_all(BINARY_MAGIC)?;
+        self.writer.write_all(&[BINARY_VERSION, kmer_size as u8, canonical as u8, self.kmer_bytes as u8])?;
+        self.writer.write_all(&(genomes.len() as u32).to_le_bytes())?;
+        for genome in genomes {
+            self.writer.write_all(&(genome.len() as u32).to_le_bytes())?;
+            self.writer.write_all(genome.as_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn write_row(&mut self, kmer: u128, counts: &[u32]) -> io::Result<()> {
+        self.writer.write_all(&kmer.to_le_bytes()[..self.kmer_bytes])?;
+        for count in counts {
+            self.writer.write_all(&count.to_le_bytes())?;
+        }
+        Ok(())
+    }
+
+    fn finish(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// A k-mer table read back from the binary format.
+#[derive(Debug, PartialEq)]
+pub struct BinaryKmerTable {
+    pub kmer_size: usize,
+    pub canonical: bool,
+    pub genomes: Vec<String>,
+    pub rows: Vec<(u128, Vec<u32>)>,
+}
+
+fn read_u32<R: io::Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+/// Read a table written by `BinaryWriter`.
+pub fn read_binary_kmer_table<R: io::Read>(reader: R) -> io::Result<BinaryKmerTable> {
+    let mut reader = io::BufReader::new(reader);
+    let mut header = [0u8; 8];
+    reader.read_exact(&mut header)?;
+    if &header[..4] != BINARY_MAGIC {
+        return Err(invalid_data("Not a binary k-mer table".to_string()))
+    }
+    if header[4] != BINARY_VERSION {
+        return Err(invalid_data(format!("Unsupported binary k-mer table version {}", header[4])))
+    }
+    let kmer_size = header[5] as usize;
+    let kmer_bytes = header[7] as usize;
+    if kmer_bytes != binary_kmer_bytes(kmer_size) {
+        return Err(invalid_data(format!(
+            "Unexpected k-mer width of {} bytes for k-mer size {}", kmer_bytes, kmer_size)))
+    }
+    let num_genomes = read_u32(&mut reader)? as usize;
+    let mut genomes = Vec::with_capacity(num_genomes);
+    for _ in 0..num_genomes {
+        let mut name = vec![0u8; read_u32(&mut reader)? as usize];
+        reader.read_exact(&mut name)?;
+        genomes.push(String::from_utf8(name).map_err(|e| invalid_data(e.to_string()))?);
+    }
+
+    let mut rows = vec!();
+    let mut kmer = [0u8; 16];
+    loop {
+        if reader.fill_buf()?.is_empty() {
+            break
+        }
+        reader.read_exact(&mut kmer[..kmer_bytes])?;
+        let mut counts = Vec::with_capacity(num_genomes);
+        for _ in 0..num_genomes {
+            counts.push(read_u32(&mut reader)?);
+        }
+        rows.push((u128::from_le_bytes(kmer), counts));
+    }
+    Ok(BinaryKmerTable {
+        kmer_size,
+        canonical: header[6] == 1,
+        genomes,
+        rows,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn write_table<K: KmerTableWriter>(writer: &mut K) {
+        let genomes = vec!["g1".to_string(), "g2".to_string()];
+        writer.write_header(&genomes, 3, true).unwrap();
+        writer.write_row(u128::encode(b"AAC").unwrap(), &[2, 0]).unwrap();
+        writer.write_row(u128::encode(b"ACG").unwrap(), &[1, 3]).unwrap();
+        writer.finish().unwrap();
+    }
+
+    #[test]
+    fn test_tsv_writer(){
+        let mut writer = TsvWriter::new(Vec::new());
+        write_table(&mut writer);
+        assert_eq!(
+            "kmer\tg1\tg2\nAAC\t2\t0\nACG\t1\t3\n",
+            String::from_utf8(writer.writer.get_ref().clone()).unwrap());
+    }
+
+    #[test]
+    fn test_matrix_market_writer(){
+        let mut writer = MatrixMarketWriter::new(Vec::new(), Vec::new()).unwrap();
+        write_table(&mut writer);
+        assert_eq!(
+            "%%MatrixMarket matrix coordinate integer general\n\
+             % kmer_size 3\n% canonical true\n% genome g1\n% genome g2\n\
+             2 2 3\n1 1 2\n2 1 1\n2 2 3\n",
+            String::from_utf8(writer.writer.get_ref().clone()).unwrap());
+        assert_eq!("AAC\nACG\n", String::from_utf8(writer.labels.get_ref().clone()).unwrap());
+    }
+
+    #[test]
+    fn test_binary_round_trip(){
+        let mut writer = BinaryWriter::new(Vec::new());
+        write_table(&mut writer);
+        let bytes = writer.writer.get_ref().clone();
+        assert_eq!(b"KMTB\x01\x03\x01\x08", &bytes[..8]);
+        assert_eq!(BinaryKmerTable {
+            kmer_size: 3,
+            canonical: true,
+            genomes: vec!["g1".to_string(), "g2".to_string()],
+            rows: vec![(u128::encode(b"AAC").unwrap(), vec![2, 0]),
+                       (u128::encode(b"ACG").unwrap(), vec![1, 3])],
+        }, read_binary_kmer_table(&bytes[..]).unwrap());
+        assert!(read_binary_kmer_table(&b"NOPE\x01\x03\x01\x08"[..]).is_err());
+    }
+}
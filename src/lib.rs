@@ -2,6 +2,23 @@
 // pub mod genome;
 // pub mod mosdepth_genome_coverage_estimators;
 pub mod genomes_and_contigs;
+pub mod compression;
+pub mod fastq;
+pub mod targets;
+pub mod external_command;
+pub mod bifrost;
+pub mod kmer;
+pub mod kmer_table;
+pub mod similarity;
+pub mod sketch;
+pub mod partitioned;
+pub mod manifest;
+pub mod taxonomy;
+pub mod representatives;
+pub mod quality;
+pub mod stats;
+pub mod quant;
+pub mod validation;
 // pub mod bam_generator;
 // pub mod filter;
 // pub mod external_command_checker;
@@ -13,18 +30,25 @@ extern crate log;
 extern crate rust_htslib;
 extern crate env_logger;
 extern crate nix;
+extern crate flate2;
+extern crate bzip2;
+extern crate xz2;
+extern crate tempfile;
 
 // use bio::io::fasta::*;
 use std::io;
 use std::str;
 use std::path::Path;
 use genomes_and_contigs::GenomesAndContigs;
+use targets::TargetNamer;
 
 use std::cmp::min;
 use std::collections;
 use std::convert::AsRef;
-use std::fs;
 use std::io::prelude::*;
+use std::fs;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::thread;
 
 
 /// A FASTA record.
@@ -32,7 +56,7 @@ use std::io::prelude::*;
 pub struct Record {
     id: String,
     desc: Option<String>,
-    seq: String,
+    seq: Vec<u8>,
 }
 
 impl Record {
@@ -41,12 +65,12 @@ impl Record {
         Record {
             id: String::new(),
             desc: None,
-            seq: String::new(),
+            seq: Vec::new(),
         }
     }
 
     /// Create a new Fasta record from given attributes.
-    pub fn with_attrs(id: &str, desc: Option<&str>, seq: String) -> Self {
+    pub fn with_attrs(id: &str, desc: Option<&str>, seq: &[u8]) -> Self {
         let desc = match desc {
             Some(desc) => Some(desc.to_owned()),
             _ => None,
@@ -54,7 +78,7 @@ impl Record {
         Record {
             id: id.to_owned(),
             desc,
-            seq: seq,
+            seq: seq.to_owned(),
         }
     }
 
@@ -88,9 +112,9 @@ impl Record {
         }
     }
 
-    /// Return the sequence of the record.
-    pub fn seq(&self) -> String {
-        self.seq.clone()
+    /// Return the sequence of the record, without line breaks.
+    pub fn seq(&self) -> &[u8] {
+        &self.seq
     }
 
     /// Clear the record.
@@ -101,20 +125,91 @@ impl Record {
     }
 }
 
-/// An iterator over the records of a Fasta file.
-pub struct Records<R: io::Read> {
-    reader: Reader<R>,
+impl SequenceRecord for Record {
+    fn id(&self) -> &str {
+        Record::id(self)
+    }
+
+    fn desc(&self) -> Option<&str> {
+        Record::desc(self)
+    }
+
+    fn seq(&self) -> &[u8] {
+        Record::seq(self)
+    }
+
+    fn is_empty(&self) -> bool {
+        Record::is_empty(self)
+    }
+}
+
+/// Accessors shared by FASTA and FASTQ records.
+pub trait SequenceRecord {
+    /// Return the id of the record.
+    fn id(&self) -> &str;
+
+    /// Return descriptions if present.
+    fn desc(&self) -> Option<&str>;
+
+    /// Return the sequence of the record, without line breaks.
+    fn seq(&self) -> &[u8];
+
+    /// Return the base qualities of the record, if the format has them.
+    fn qual(&self) -> Option<&[u8]> {
+        None
+    }
+
+    /// Check if record is empty, which signals the end of the input.
+    fn is_empty(&self) -> bool;
+}
+
+/// A reader of FASTA or FASTQ records, so that callers can iterate over
+/// either format.
+pub trait SequenceRead {
+    type Record: SequenceRecord + Default;
+
+    /// Read the next record into the given one, leaving it empty at the end
+    /// of the input.
+    fn read(&mut self, record: &mut Self::Record) -> io::Result<()>;
+
+    /// Read the next record into the given one, returning false at the end of
+    /// the input. Reusing one record across calls avoids allocating for each
+    /// record:
+    ///
+    /// ```ignore
+    /// let mut record = Record::new();
+    /// while reader.read_next(&mut record)? {
+    ///     process(record.seq());
+    /// }
+    /// ```
+    fn read_next(&mut self, record: &mut Self::Record) -> io::Result<bool> {
+        self.read(record)?;
+        Ok(!record.is_empty())
+    }
+
+    /// Return an iterator over the remaining records.
+    fn sequence_records(self) -> SequenceRecords<Self> where Self: Sized {
+        SequenceRecords {
+            reader: self,
+            error_has_occured: false,
+        }
+    }
+}
+
+/// An iterator over the records of a FASTA or FASTQ reader.
+pub struct SequenceRecords<S: SequenceRead> {
+    reader: S,
     error_has_occured: bool,
 }
 
-impl<R: io::Read> Iterator for Records<R> {
-    type Item = io::Result<Record>;
+impl<S: SequenceRead> Iterator for SequenceRecords<S> {
+    type Item = io::Result<S::Record>;
 
-    fn next(&mut self) -> Option<io::Result<Record>> {
+    fn next(&mut self) -> Option<io::Result<S::Record>> {
         if self.error_has_occured {
             None
         } else {
-            let mut record = Record::new();
+            let mut record = S::Record::default();
             match self.reader.read(&mut record) {
                 Ok(()) if record.is_empty() => None,
                 Ok(()) => Some(Ok(record)),
@@ -127,17 +222,48 @@ impl<R: io::Read> Iterator for Records<R> {
     }
 }
 
+/// An iterator over the records of a Fasta file.
+pub type Records<R> = SequenceRecords<Reader<R>>;
+
+/// Sequence file formats, as recognised from the first record.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SequenceFormat {
+    Fasta,
+    Fastq,
+}
+
+impl SequenceFormat {
+    /// Determine the format of a possibly compressed sequence file from the
+    /// first character of its first non-blank line.
+    pub fn of_file<P: AsRef<Path>>(path: P) -> io::Result<SequenceFormat> {
+        let reader = io::BufReader::new(compression::open_file(path)?);
+        for byte in reader.bytes() {
+            match byte? {
+                b'>' => return Ok(SequenceFormat::Fasta),
+                b'@' => return Ok(SequenceFormat::Fastq),
+                b'\n' | b'\r' | b' ' | b'\t' => continue,
+                _ => break
+            }
+        }
+        Err(io::Error::new(
+            io::ErrorKind::InvalidData,
+            "Expected FASTA or FASTQ input starting with > or @.",
+        ))
+    }
+}
+
 /// A FASTA reader.
 #[derive(Debug)]
 pub struct Reader<R: io::Read> {
     reader: io::BufReader<R>,
-    line: String,
+    line: Vec<u8>,
 }
 
-impl Reader<fs::File> {
-    /// Read FASTA from given file path.
+impl Reader<Box<dyn io::Read + Send>> {
+    /// Read FASTA from given file path, transparently decompressing gzip,
+    /// bgzip, bzip2 or xz input.
     pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
-        fs::File::open(path).map(Reader::new)
+        compression::open_file(path).map(Reader::new)
     }
 }
 
@@ -158,7 +284,7 @@ impl<R: io::Read> Reader<R> {
     pub fn new(reader: R) -> Self {
         Reader {
             reader: io::BufReader::new(reader),
-            line: String::new(),
+            line: Vec::new(),
         }
     }
 
@@ -184,37 +310,37 @@ impl<R: io::Read> Reader<R> {
     /// ```
     pub fn read(&mut self, record: &mut Record) -> io::Result<()> {
         record.clear();
-        if self.line.is_empty() {
-            try!(self.reader.read_line(&mut self.line));
-            if self.line.is_empty() {
+        // Blank lines before the first record are skipped, as they are when
+        // detecting the format.
+        while trim_line_ending(&self.line).is_empty() {
+            self.line.clear();
+            if self.reader.read_until(b'\n', &mut self.line)? == 0 {
                 return Ok(());
             }
         }
 
-        if !self.line.starts_with('>') {
-            return Err(io::Error::new(
-                io::ErrorKind::Other,
-                "Expected > at record start.",
-            ));
+        if !self.line.starts_with(b">") {
+            return Err(invalid_data("Expected > at record start."));
+        }
+        {
+            let header = match str::from_utf8(trim_line_ending(&self.line[1..])) {
+                Ok(header) => header,
+                Err(_) => return Err(io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    "Non-UTF8 characters found in FASTA header.",
+                ))
+            };
+            let mut fields = header.splitn(2, ' ');
+            record.id.push_str(fields.next().unwrap());
+            record.desc = fields.next().map(|s| s.to_owned());
         }
-        record.id = self.line[1..]
-            .trim_right()
-            .splitn(2, ' ')
-            .nth(0)
-            .map(|s| s.to_owned())
-            .unwrap();
-        record.desc = self.line[1..]
-            .trim_right()
-            .splitn(2, ' ')
-            .nth(1)
-            .map(|s| s.to_owned());
         loop {
             self.line.clear();
-            try!(self.reader.read_line(&mut self.line));
-            if self.line.is_empty() || self.line.starts_with('>') {
+            try!(self.reader.read_until(b'\n', &mut self.line));
+            if self.line.is_empty() || self.line.starts_with(b">") {
                 break;
             }
-            record.seq.push_str(&self.line);
+            record.seq.extend_from_slice(trim_line_ending(&self.line));
         }
 
         Ok(())
@@ -241,56 +367,279 @@ impl<R: io::Read> Reader<R> {
     /// # }
     /// ```
     pub fn records(self) -> Records<R> {
-        Records {
-            reader: self,
-            error_has_occured: false,
+        self.sequence_records()
+    }
+}
+
+impl<R: io::Read> SequenceRead for Reader<R> {
+    type Record = Record;
+
+    fn read(&mut self, record: &mut Record) -> io::Result<()> {
+        Reader::read(self, record)
+    }
+}
+
+
+
+/// A FASTA writer.
+#[derive(Debug)]
+pub struct Writer<W: io::Write> {
+    writer: io::BufWriter<W>,
+    line_width: Option<usize>,
+}
+
+impl Writer<fs::File> {
+    /// Write to the given file path.
+    pub fn to_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+        fs::File::create(path).map(Writer::new)
+    }
+}
+
+impl<W: io::Write> Writer<W> {
+    /// Create a new Fasta writer which writes each sequence on a single line.
+    pub fn new(writer: W) -> Self {
+        Writer {
+            writer: io::BufWriter::new(writer),
+            line_width: None,
         }
     }
+
+    /// Create a new Fasta writer which wraps sequences at the given number
+    /// of bases per line. A width of 0 disables wrapping.
+    pub fn with_line_width(writer: W, line_width: usize) -> Self {
+        Writer {
+            writer: io::BufWriter::new(writer),
+            line_width: match line_width {
+                0 => None,
+                width => Some(width)
+            },
+        }
+    }
+
+    /// Directly write a Fasta record.
+    pub fn write_record(&mut self, record: &Record) -> io::Result<()> {
+        self.write(record.id(), record.desc(), record.seq())
+    }
+
+    /// Write a Fasta record with given id, optional description and sequence.
+    pub fn write(&mut self, id: &str, desc: Option<&str>, seq: &[u8]) -> io::Result<()> {
+        self.writer.write_all(b">")?;
+        self.writer.write_all(id.as_bytes())?;
+        if let Some(desc) = desc {
+            self.writer.write_all(b" ")?;
+            self.writer.write_all(desc.as_bytes())?;
+        }
+        self.writer.write_all(b"\n")?;
+
+        match self.line_width {
+            Some(width) => {
+                for line in seq.chunks(width) {
+                    self.writer.write_all(line)?;
+                    self.writer.write_all(b"\n")?;
+                }
+            },
+            None => {
+                self.writer.write_all(seq)?;
+                self.writer.write_all(b"\n")?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Flush the writer, ensuring that everything is written.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.writer.flush()
+    }
+}
+
+/// Remove any trailing newline and carriage return characters from a line.
+pub fn trim_line_ending(line: &[u8]) -> &[u8] {
+    let mut end = line.len();
+    while end > 0 && (line[end-1] == b'\n' || line[end-1] == b'\r') {
+        end -= 1;
+    }
+    &line[..end]
 }
 
+/// Return an `InvalidData` error, as for malformed input.
+pub fn invalid_data<E: Into<Box<dyn std::error::Error + Send + Sync>>>(error: E) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, error)
+}
 
+/// Open a file, naming it and what it holds in any error, e.g. "Could not
+/// open taxonomy <path>: <reason>".
+pub fn open_with_context<P: AsRef<Path>>(path: P, what: &str) -> io::Result<fs::File> {
+    let path = path.as_ref();
+    fs::File::open(path).map_err(|e| io::Error::new(
+        e.kind(), format!("Could not open {} {}: {}", what, path.display(), e)))
+}
 
-pub fn read_genome_fasta_files(fasta_file_paths: &[&str]) -> GenomesAndContigs {
-    let mut contig_to_genome = GenomesAndContigs::new();
-    let mut reader;
-    for file in fasta_file_paths {
-        let path = Path::new(*file);
-        reader = Reader::from_file(path);
-        let records = reader.unwrap().records();
-        for record in records {
-            let genome = record.unwrap();
-            contig_to_genome.genomes.push(genome.id().to_string());
-            contig_to_genome.contig_to_genome.push(genome.seq().to_string());
-            
+/// Derive a genome name from the path of its FASTA file, i.e. the file name
+/// with any compression and FASTA or FASTQ extensions removed.
+pub fn genome_name_from_path(path: &str) -> String {
+    let file_name = Path::new(path)
+        .file_name()
+        .map(|f| f.to_string_lossy().into_owned())
+        .unwrap_or(path.to_string());
+    let mut file_name = file_name.as_str();
+    for extension in compression::COMPRESSED_EXTENSIONS.iter() {
+        if file_name.ends_with(extension) {
+            file_name = &file_name[..file_name.len()-extension.len()];
+            break;
         }
     }
-    return contig_to_genome;
+    for extension in [".fna", ".fasta", ".fa", ".fas", ".fsa", ".fastq", ".fq"].iter() {
+        if file_name.ends_with(extension) && file_name.len() > extension.len() {
+            return file_name[..file_name.len()-extension.len()].to_string();
+        }
+    }
+    file_name.to_string()
 }
 
+/// Read each file as one genome, named after the file, with each record
+/// inserted as one of its contigs. Errors name the file which could not be
+/// read.
+pub fn read_genome_fasta_files(fasta_file_paths: &[&str]) -> io::Result<GenomesAndContigs> {
+    let mut contig_to_genome = GenomesAndContigs::new();
+    for file in fasta_file_paths {
+        contig_to_genome.establish_genome(genome_name_from_path(file));
+        read_contigs_into(&mut contig_to_genome, file)?;
+    }
+    Ok(contig_to_genome)
+}
 
-pub fn read_genome_fasta_files_as_one_genome(fasta_file_paths: &[&str]) -> GenomesAndContigs {
+/// Read each file as one genome, named after its full path, for k-mer
+/// counting.
+pub fn read_genome_fasta_files_as_one_genome(fasta_file_paths: &[&str]) -> io::Result<GenomesAndContigs> {
     let mut contig_to_genome = GenomesAndContigs::new();
-//    let mut reader;
     for file in fasta_file_paths {
-        let path = Path::new(*file);
-        let mut reader = Reader::from_file(path);
-        let mut name = file.to_string();
-//        name = name.last().unwrap().split(".");
-        contig_to_genome.genomes.push(name);
-        let records = reader.unwrap().records();
-        let mut genome = Vec::new();
-        for record in records {
-            let contig = record.unwrap();
-            genome.push(contig.seq().to_string());
+        contig_to_genome.establish_genome(file.to_string());
+        read_contigs_into(&mut contig_to_genome, file)?;
+    }
+    Ok(contig_to_genome)
+}
+
+/// Read each file as one genome with the name it is paired with, given as
+/// `(name, path)`, so that genomes listed in a manifest keep their
+/// accessions.
+pub fn read_named_genome_fasta_files(genome_files: &[(&str, &str)]) -> io::Result<GenomesAndContigs> {
+    let mut contig_to_genome = GenomesAndContigs::new();
+    for &(name, file) in genome_files {
+        contig_to_genome.establish_genome(name.to_string());
+        read_contigs_into(&mut contig_to_genome, file)?;
+    }
+    Ok(contig_to_genome)
+}
+
+/// Read named genome files as `read_named_genome_fasta_files` does, with
+/// files read in parallel by the given number of threads. Genomes are kept
+/// in the order given. After a file fails to be read no further files are
+/// started.
+pub fn read_named_genome_fasta_files_in_threads(genome_files: &[(&str, &str)], threads: usize)
+    -> io::Result<GenomesAndContigs> {
+    if threads <= 1 {
+        return read_named_genome_fasta_files(genome_files)
+    }
+    let next_path = AtomicUsize::new(0);
+    let failed = AtomicBool::new(false);
+    let mut genomes: Vec<Option<io::Result<GenomesAndContigs>>> =
+        (0..genome_files.len()).map(|_| None).collect();
+    thread::scope(|scope| {
+        let workers: Vec<_> = (0..threads).map(|_| scope.spawn(|| {
+            let mut genomes = vec!();
+            while !failed.load(Ordering::SeqCst) {
+                let i = next_path.fetch_add(1, Ordering::SeqCst);
+                if i >= genome_files.len() {
+                    break
+                }
+                let genome = read_named_genome_fasta_files(&genome_files[i..i+1]);
+                if genome.is_err() {
+                    failed.store(true, Ordering::SeqCst);
+                }
+                genomes.push((i, genome));
+            }
+            genomes
+        })).collect();
+        for worker in workers {
+            for (i, genome) in worker.join().expect("FASTA reading thread panicked") {
+                genomes[i] = Some(genome);
+            }
+        }
+    });
+    // Files are started in order, so every genome before the first failure
+    // has been read and that error is returned before any missing genome is
+    // reached.
+    let mut contig_to_genome = GenomesAndContigs::new();
+    for genome in genomes {
+        match genome {
+            Some(genome) => contig_to_genome.append(genome?),
+            None => return Err(io::Error::other("A genome was not read after an earlier error")),
         }
-        contig_to_genome.contig_to_genome.push(genome.join(""));
     }
-    return contig_to_genome;
+    Ok(contig_to_genome)
+}
+
+fn read_contigs_into(contig_to_genome: &mut GenomesAndContigs, file: &str) -> io::Result<()> {
+    let path = Path::new(file);
+    let result = SequenceFormat::of_file(path).and_then(|format| match format {
+        SequenceFormat::Fasta => insert_records(
+            contig_to_genome, Reader::from_file(path)?),
+        SequenceFormat::Fastq => insert_records(
+            contig_to_genome, fastq::Reader::from_file(path)?),
+    });
+    result.map_err(|e| io::Error::new(e.kind(), format!("{}: {}", file, e)))
+}
+
+fn insert_records<S: SequenceRead>(contig_to_genome: &mut GenomesAndContigs, mut reader: S) -> io::Result<()> {
+    let mut contig = S::Record::default();
+    while reader.read_next(&mut contig)? {
+        contig_to_genome.insert_with_sequence(contig.id().to_string(), contig.seq().to_vec());
+    }
+    Ok(())
+}
+
+/// Copy the records of each file to the writer one at a time, so that memory
+/// use is bounded by the longest record rather than the size of the input.
+/// Records are renamed by the namer, with each file treated as one genome
+/// of the name it is paired with, given as `(name, path)`. Returns the
+/// number of records written.
+pub fn write_genome_fasta_files<W: io::Write, M: io::Write>(
+    genome_files: &[(&str, &str)], writer: &mut Writer<W>, namer: &mut TargetNamer<M>)
+    -> io::Result<usize> {
+
+    let mut num_records = 0;
+    for &(genome, file) in genome_files {
+        let path = Path::new(file);
+        num_records += match SequenceFormat::of_file(path)? {
+            SequenceFormat::Fasta => copy_records(
+                Reader::from_file(path)?, genome, writer, namer)?,
+            SequenceFormat::Fastq => copy_records(
+                fastq::Reader::from_file(path)?, genome, writer, namer)?,
+        };
+    }
+    writer.flush()?;
+    namer.flush()?;
+    Ok(num_records)
+}
+
+fn copy_records<S: SequenceRead, W: io::Write, M: io::Write>(
+    mut reader: S, genome: &str, writer: &mut Writer<W>, namer: &mut TargetNamer<M>)
+    -> io::Result<usize> {
+    let mut record = S::Record::default();
+    let mut num_records = 0;
+    while reader.read_next(&mut record)? {
+        let target = namer.name_target(genome, record.id())?;
+        writer.write(&target, None, record.seq())?;
+        num_records += 1;
+    }
+    Ok(num_records)
 }
 
 #[cfg(test)]
 mod tests {
     use super::*;
+    use kmer::Kmer;
 
     #[test]
     fn test_contig_to_genome(){
@@ -301,12 +650,229 @@ mod tests {
         assert_eq!(
             String::from("genome0"),
             *(contig_to_genome.genome_of_contig(&String::from("contig1")).unwrap()));
+        assert_eq!(Some(index), contig_to_genome.genome_index_of_contig(&String::from("contig1")));
+        assert_eq!(None, contig_to_genome.genome_of_contig(&String::from("contig2")));
+    }
+
+    #[test]
+    fn test_contig_name_shared_between_genomes(){
+        let mut contig_to_genome = GenomesAndContigs::new();
+        contig_to_genome.establish_genome(String::from("genome0"));
+        contig_to_genome.insert(String::from("contig_1"));
+        contig_to_genome.insert(String::from("contig_2"));
+        contig_to_genome.establish_genome(String::from("genome1"));
+        let index = contig_to_genome.insert(String::from("contig_1"));
+        contig_to_genome.establish_genome(String::from("genome2"));
+        contig_to_genome.insert(String::from("contig_1"));
+        assert_eq!(None, contig_to_genome.genome_index_of_contig(&String::from("contig_1")));
+        assert_eq!(Some(0), contig_to_genome.genome_index_of_contig(&String::from("contig_2")));
+        assert_eq!(Some(1), contig_to_genome.genome_index_of_contig_index(index));
+        assert_eq!(4, contig_to_genome.num_contigs());
+    }
+
+    #[test]
+    fn test_contigs_of_genome(){
+        let mut contig_to_genome = GenomesAndContigs::new();
+        contig_to_genome.establish_genome(String::from("genome0"));
+        contig_to_genome.insert(String::from("contig1"));
+        contig_to_genome.insert(String::from("contig2"));
+        let index = contig_to_genome.establish_genome(String::from("genome1"));
+        contig_to_genome.insert(String::from("contig3"));
+        assert_eq!(
+            Some(vec![&String::from("contig1"), &String::from("contig2")]),
+            contig_to_genome.contigs_of_genome(&String::from("genome0")));
+        assert_eq!(Some(index), contig_to_genome.genome_index(&String::from("genome1")));
+        assert_eq!(Some(&vec![2]), contig_to_genome.contig_indices_of_genome(index));
+        assert_eq!(None, contig_to_genome.contigs_of_genome(&String::from("genome2")));
     }
 
     #[test]
     fn test_read_genome_fasta_files_one_genome(){
-        let contig_to_genome = read_genome_fasta_files(&vec!["tests/data/genome1.fna"]);
+        let contig_to_genome = read_genome_fasta_files(&["tests/data/genome1.fna"]).unwrap();
         assert_eq!(String::from("genome1"), *contig_to_genome.genome_of_contig(&String::from("seq1")).unwrap());
         assert_eq!(String::from("genome1"), *contig_to_genome.genome_of_contig(&String::from("seq2")).unwrap());
     }
+
+    #[test]
+    fn test_read_compressed_genome_fasta_files(){
+        let contig_to_genome = read_genome_fasta_files(
+            &["tests/data/genome1.fna.gz", "tests/data/genome1_multimember.fna.gz"]).unwrap();
+        assert_eq!(vec!["genome1", "genome1_multimember"], contig_to_genome.genomes);
+        assert_eq!(vec!["seq1", "seq2", "seq1", "seq2"], contig_to_genome.contigs);
+        let contig_to_genome = read_genome_fasta_files_as_one_genome(
+            &["tests/data/genome1.fna.bz2", "tests/data/genome1.fna.xz"]).unwrap();
+        assert_eq!(vec![b"aa".to_vec(), b"tt".to_vec(), b"aa".to_vec(), b"tt".to_vec()], contig_to_genome.sequences);
+    }
+
+    #[test]
+    fn test_read_strips_line_endings(){
+        const FASTA: &[u8] = b">id1 desc\r\nAC\r\nGT\r\n>id2\nTT\nA";
+        let mut reader = Reader::new(FASTA);
+        let mut record = Record::new();
+        assert!(reader.read_next(&mut record).unwrap());
+        assert_eq!("id1", record.id());
+        assert_eq!(Some("desc"), record.desc());
+        assert_eq!(b"ACGT", record.seq());
+        assert!(reader.read_next(&mut record).unwrap());
+        assert_eq!("id2", record.id());
+        assert_eq!(None, record.desc());
+        assert_eq!(b"TTA", record.seq());
+        assert!(!reader.read_next(&mut record).unwrap());
+    }
+
+    #[test]
+    fn test_read_fastq_genome_files(){
+        let contig_to_genome = read_genome_fasta_files(&["tests/data/reads_for_seq1.fq"]).unwrap();
+        assert_eq!(vec!["reads_for_seq1"], contig_to_genome.genomes);
+        assert_eq!(12, contig_to_genome.num_contigs());
+        assert_eq!(SequenceFormat::Fastq, SequenceFormat::of_file("tests/data/reads_for_seq1.fq").unwrap());
+        assert_eq!(SequenceFormat::Fasta, SequenceFormat::of_file("tests/data/genome1.fna.gz").unwrap());
+    }
+
+    #[test]
+    fn test_writer(){
+        let mut writer = Writer::new(Vec::new());
+        writer.write("id1", Some("desc"), b"ACGTACGT").unwrap();
+        writer.write_record(&Record::with_attrs("id2", None, b"")).unwrap();
+        writer.flush().unwrap();
+        assert_eq!(b">id1 desc\nACGTACGT\n>id2\n\n".to_vec(), *writer.writer.get_ref());
+
+        let mut writer = Writer::with_line_width(Vec::new(), 3);
+        writer.write("id1", None, b"ACGTACG").unwrap();
+        writer.write("id2", None, b"ACG").unwrap();
+        writer.flush().unwrap();
+        assert_eq!(b">id1\nACG\nTAC\nG\n>id2\nACG\n".to_vec(), *writer.writer.get_ref());
+    }
+
+    #[test]
+    fn test_writer_round_trip(){
+        let contig_to_genome = read_genome_fasta_files(&["tests/data/7seqs.fna"]).unwrap();
+        let mut writer = Writer::with_line_width(Vec::new(), 60);
+        for (contig, sequence) in contig_to_genome.contigs.iter().zip(contig_to_genome.sequences.iter()) {
+            writer.write(contig, None, sequence).unwrap();
+        }
+        writer.flush().unwrap();
+        let written: Vec<Record> = Reader::new(&writer.writer.get_ref()[..]).records()
+            .map(|r| r.unwrap()).collect();
+        assert_eq!(contig_to_genome.contigs.len(), written.len());
+        for (record, sequence) in written.iter().zip(contig_to_genome.sequences.iter()) {
+            assert_eq!(&sequence[..], record.seq());
+        }
+    }
+
+    #[test]
+    fn test_write_genome_fasta_files(){
+        let mut writer = Writer::new(Vec::new());
+        let mut namer = TargetNamer::new(targets::TargetNaming::Contig, Vec::new()).unwrap();
+        let num_records = write_genome_fasta_files(
+            &[("genome1", "tests/data/genome1.fna.gz"), ("reads", "tests/data/reads_for_seq1_1.fq")],
+            &mut writer, &mut namer).unwrap();
+        assert_eq!(8, num_records);
+        let written = writer.writer.get_ref();
+        assert!(written.starts_with(b">seq1\naa\n>seq2\ntt\n>1/1\nGGGTACACTT"));
+    }
+
+    #[test]
+    fn test_write_genome_fasta_files_prefixed_targets(){
+        let mut writer = Writer::new(Vec::new());
+        let mut namer = TargetNamer::new(targets::TargetNaming::GenomeContig, Vec::new()).unwrap();
+        write_genome_fasta_files(
+            &[("genome1", "tests/data/genome1.fna"), ("GCF_1", "tests/data/genome1_multimember.fna.gz")],
+            &mut writer, &mut namer).unwrap();
+        let written = String::from_utf8(writer.writer.get_ref().clone()).unwrap();
+        assert_eq!(
+            ">genome1|seq1\naa\n>genome1|seq2\ntt\n\
+             >GCF_1|seq1\naa\n>GCF_1|seq2\ntt\n",
+            written);
+
+        let mut namer = TargetNamer::new(targets::TargetNaming::Contig, Vec::new()).unwrap();
+        assert!(write_genome_fasta_files(
+            &[("genome1", "tests/data/genome1.fna"), ("GCF_1", "tests/data/genome1_multimember.fna.gz")],
+            &mut Writer::new(Vec::new()), &mut namer).is_err());
+    }
+
+    #[test]
+    fn test_establish_kmers_within_contigs(){
+        let contig_to_genome = read_genome_fasta_files_as_one_genome(&["tests/data/genome1.fna"]).unwrap();
+        let kmer_map = contig_to_genome.establish_kmers::<u64>(2, true);
+        assert_eq!(1, kmer_map.window_counts.len());
+        assert_eq!(2, kmer_map.window_counts[0].counted);
+        assert_eq!(1, kmer_map.window_counts[0].skipped_contig_boundary);
+        let kmers: Vec<String> = kmer_map.sorted_kmers().into_iter()
+            .map(|k| kmer_map.decode(k)).collect();
+        assert_eq!(vec!["AA"], kmers);
+        assert_eq!(vec![2], kmer_map.kmers[&u64::encode(b"AA").unwrap()]);
+    }
+
+    #[test]
+    fn test_kmers_in_threads_match_serial(){
+        let paths = vec!["tests/data/genome1.fna", "tests/data/7seqs.fna",
+                         "tests/data/genome1.fna.gz", "tests/data/genome1.fna.bz2"];
+        let serial = read_genome_fasta_files_as_one_genome(&paths).unwrap();
+        let named: Vec<(&str, &str)> = paths.iter().map(|p| (*p, *p)).collect();
+        let parallel = read_named_genome_fasta_files_in_threads(&named, 3).unwrap();
+        assert_eq!(serial.genomes, parallel.genomes);
+        assert_eq!(serial.contigs, parallel.contigs);
+        assert_eq!(serial.sequences, parallel.sequences);
+        assert_eq!(2, parallel.contig_indices_of_genome(1).unwrap()[0]);
+
+        let serial = serial.establish_kmers::<u64>(5, true);
+        let parallel = parallel.establish_kmers_in_threads::<u64>(5, true, 3);
+        assert_eq!(serial.genomes, parallel.genomes);
+        assert_eq!(serial.kmers, parallel.kmers);
+        assert_eq!(serial.window_counts, parallel.window_counts);
+    }
+
+    #[test]
+    fn test_read_in_threads_returns_first_error(){
+        let mut genome_files = vec![("g", "tests/data/genome1.fna"); 6];
+        genome_files[2] = ("missing", "tests/data/missing.fna");
+        let error = read_named_genome_fasta_files_in_threads(&genome_files, 3).unwrap_err();
+        assert!(error.to_string().contains("tests/data/missing.fna"));
+    }
+
+    #[test]
+    fn test_unique_kmers(){
+        let mut contig_to_genome = GenomesAndContigs::new();
+        contig_to_genome.establish_genome("g1".to_string());
+        contig_to_genome.insert_with_sequence("c1".to_string(), b"AAAC".to_vec());
+        contig_to_genome.establish_genome("g2".to_string());
+        contig_to_genome.insert_with_sequence("c2".to_string(), b"AAAG".to_vec());
+        contig_to_genome.establish_genome("g3".to_string());
+        contig_to_genome.insert_with_sequence("c3".to_string(), b"AACC".to_vec());
+        let kmer_map = contig_to_genome.establish_kmers::<u64>(3, false);
+
+        let decoded = |unique: Vec<Vec<(u64, u32)>>| -> Vec<Vec<(String, u32)>> {
+            unique.into_iter()
+                .map(|kmers| kmers.into_iter().map(|(k, c)| (k.decode(3), c)).collect())
+                .collect()
+        };
+        assert_eq!(vec![vec!(), vec![("AAG".to_string(), 1)], vec![("ACC".to_string(), 1)]],
+                   decoded(kmer_map.unique_kmers(0)));
+        assert_eq!(vec![vec![("AAA".to_string(), 1), ("AAC".to_string(), 1)],
+                        vec![("AAA".to_string(), 1), ("AAG".to_string(), 1)],
+                        vec![("AAC".to_string(), 1), ("ACC".to_string(), 1)]],
+                   decoded(kmer_map.unique_kmers(1)));
+
+        let mut output = Vec::new();
+        kmer_map.write_unique_kmers(0, &mut output).unwrap();
+        assert_eq!("genome\tkmer\tcount\ng2\tAAG\t1\ng3\tACC\t1\n",
+                   String::from_utf8(output).unwrap());
+    }
+
+    #[test]
+    fn test_open_with_context(){
+        assert!(open_with_context("tests/data/genome1.fna", "genome").is_ok());
+        let error = open_with_context("tests/data/missing.tsv", "taxonomy").unwrap_err();
+        assert_eq!(io::ErrorKind::NotFound, error.kind());
+        assert!(error.to_string().starts_with("Could not open taxonomy tests/data/missing.tsv: "));
+    }
+
+    #[test]
+    fn test_genome_name_from_path(){
+        assert_eq!("genome1", genome_name_from_path("tests/data/genome1.fna"));
+        assert_eq!("GCF_000005845.2_genomic", genome_name_from_path("a/GCF_000005845.2_genomic.fna.gz"));
+        assert_eq!("genome1", genome_name_from_path("genome1.fa.xz"));
+        assert_eq!("genome1.txt", genome_name_from_path("genome1.txt"));
+    }
 }
@@ -14,17 +14,37 @@ use log::LogLevelFilter;
 extern crate env_logger;
 use env_logger::LogBuilder;
 
-use kmer_indexer::genomes_and_contigs;
+use kmer_indexer::genomes_and_contigs::KmerMap;
+use kmer_indexer::external_command::{self, ExternalCommandError};
+use kmer_indexer::bifrost::BifrostBuilder;
+use kmer_indexer::kmer::{self, Kmer, WindowCounts};
+use kmer_indexer::kmer_table::{self, KmerTableFormat, KmerTableWriter};
+use kmer_indexer::manifest::{self, GenomeManifestEntry, PathResolution};
+use kmer_indexer::partitioned::{self, PartitionedCounter};
+use kmer_indexer::similarity::Similarities;
+use kmer_indexer::sketch::{self, SketchKind, SketchParameters};
+use kmer_indexer::quality::{self, QualityThresholds};
+use kmer_indexer::quant::{self, KallistoQuantBuilder, QuantReads};
+use kmer_indexer::representatives::{self, RepresentativeSelection, SpeciesClusters};
+use kmer_indexer::stats::{self, StatsFormat};
+use kmer_indexer::taxonomy::{self, TaxonFilter};
+use kmer_indexer::validation;
+use kmer_indexer::targets::{self, TargetNaming, TargetNamer};
 use tempfile::{tempfile, tempdir, Builder};
-use std::io::{self, Write, Read};
+use std::io::{self, Write};
 use std::env;
 use std::str;
 use std::process::{self, Command};
 use std::collections::HashSet;
-use std::path::Path;
+use std::path::{Path, PathBuf};
 use std::error::Error;
 use std::ffi::OsString;
 use std::fs::{self, File};
+use std::os::unix::fs::OpenOptionsExt;
+use std::thread;
+use nix::fcntl;
+use nix::sys::stat;
+use nix::unistd::mkfifo;
 use csv::{Writer, ReaderBuilder};
 
 fn main() {
@@ -34,153 +54,162 @@ fn main() {
     match matches.subcommand_name() {
         Some("kallisto") => {
             let m = matches.subcommand_matches("kallisto").unwrap();
-            let genomes_and_contigs;
-            let mut genomes_string = String::new();
-            if m.is_present("fasta-files") {
-                println!("Here first!");
-                let genome_fasta_files: Vec<&str> = m.values_of("fasta-files").unwrap().collect();
-                genomes_and_contigs = kmer_indexer::read_genome_fasta_files(&genome_fasta_files);
-                for (i, value) in genomes_and_contigs.contig_to_genome.iter().enumerate() {
-                    genomes_string = [genomes_string, value.clone()].join(&format!(">{}\n", genomes_and_contigs.genomes[i]));
-                    genomes_string.push_str("\n")
-                }
-                run_kallisto(genomes_string, m.clone());
-            } else if m.is_present("fasta-directory") {
-                println!("Here second!");
-                // println!("{}",format!("{:?}", m.value_of("fasta-directory").unwrap()));
-                let mut file_path = File::open(m.value_of("fasta-directory").unwrap()).unwrap();
-                // println!("{}",format!("{:?}", file_path));
-                let mut contents = String::new();
-                file_path.read_to_string(&mut contents);
-                // println!("{}",format!("{:?}", contents));
-                let mut rdr = csv::ReaderBuilder::new()
-                    .delimiter(b'\t')
-                    .has_headers(false)
-                    .from_reader(contents.as_bytes());
-                let mut genome_fasta_files: Vec<String> = Vec::new();
-                for line in rdr.records() {
-                    let record = line.unwrap();
-                    // println!("{}",format!("{:?}", record));
-                    let file = &record[1];
-                    // let mut s = String::from(file);
-                    let file_split: Vec<&str> = file.split("/").collect();
-                    // println!("{}",format!("{:?}", file_split));
-                    let file_name = format!("{}_genomic.fna", file_split.last().unwrap());
-                    // println!("{}",format!("{:?}", file_name));
-                    genome_fasta_files.push([file.to_string(), file_name].join("/"));
-                }
-                let mut strs: Vec<&str> = vec!();
-                for f in &genome_fasta_files {
-                    strs.push(f);
-                }
-                // info!("{:?}", &strs);
-                genomes_and_contigs = kmer_indexer::read_genome_fasta_files(&strs);
-                for (i, value) in genomes_and_contigs.contig_to_genome.iter().enumerate() {
-                    genomes_string = [genomes_string, value.clone()].join(&format!(">{}\n", genomes_and_contigs.genomes[i]));
-                    genomes_string.push_str("\n")
-                }
-                run_kallisto(genomes_string, m.clone());
-            }
+            set_log_level(m);
+            let genome_files = genome_files(m, kmer_indexer::genome_name_from_path);
+            let genome_files = as_str_pairs(&genome_files);
+            preflight_or_exit(&genome_files);
+            exit_on_external_command_error(run_kallisto(&genome_files, m.clone()));
         }
         Some("bifrost") => {
             let m = matches.subcommand_matches("bifrost").unwrap();
-            // println!("{}",format!("{:?}", m.value_of("fasta-directory")));
-            let mut file_path = File::open(m.value_of("fasta-directory").unwrap().to_string()).unwrap();
-            // println!("{}",format!("{:?}", file_path));
-            let mut contents = String::new();
-            file_path.read_to_string(&mut contents);
-            // println!("{}",format!("{:?}", contents));
-            let mut rdr = csv::ReaderBuilder::new()
-                .delimiter(b'\t')
-                .has_headers(false)
-                .from_reader(contents.as_bytes());
-            let mut rep_path = File::open(m.value_of("representatives").unwrap()).unwrap();
-            let mut rep_contents = String::new();
-            rep_path.read_to_string(&mut rep_contents);
-            // println!("{}",format!("{:?}", contents));
-            let mut rep_rdr = csv::ReaderBuilder::new()
-                .delimiter(b'\t')
-                .has_headers(true)
-                .from_reader(rep_contents.as_bytes());
-            let mut RepRecord: HashSet<String> = HashSet::new();
-            for line in rep_rdr.records() {
-                let record = line.unwrap();
-                RepRecord.insert(record[0].to_string());
-            }
+            set_log_level(m);
+            let clusters = read_representatives_or_exit(m).unwrap();
+            let entries = clusters.select(read_manifest_or_exit(m), representative_selection(m));
             let mut genome_fasta_files: Vec<String> = Vec::new();
             let mut rep_fasta_files: Vec<String> = Vec::new();
 
-            for line in rdr.records() {
-                let record = line.unwrap();
-                // println!("{}",format!("{:?}", record));
-                let file = &record[1];
-                // let mut s = String::from(file);
-                let file_split: Vec<&str> = file.split("/").collect();
-                // println!("{}",format!("{:?}", file_split));
-                let file_name = format!("{}_genomic.fna\n", file_split.last().unwrap());
-                // println!("{}",format!("{:?}", file_name));
-                if RepRecord.contains(&record[0]) {
-                    rep_fasta_files.push([file.to_string(), file_name].join("/"));
+            for entry in entries {
+                if clusters.is_representative(&entry.accession) {
+                    rep_fasta_files.push(entry.path_string());
                 } else {
-                    genome_fasta_files.push([file.to_string(), file_name].join("/"));
+                    genome_fasta_files.push(entry.path_string());
                 }
             }
-            let mut gen_file = File::create("genome_list.txt").unwrap();
-            for f in &genome_fasta_files {
-                gen_file.write_all(f.as_bytes());
-            }
-            gen_file.flush();
-
-            let mut rep_file = File::create("rep_genome_list.txt").unwrap();
-            for f in &rep_fasta_files {
-                rep_file.write_all(f.as_bytes());
-            }
-            gen_file.flush();
-            // info!("{:?}", &strs);
-            run_bifrost(m.clone());
+            // Check both lists together so that every problem is reported.
+            let all_files: Vec<(&str, &str)> = genome_fasta_files.iter().chain(rep_fasta_files.iter())
+                .map(|f| (f.as_str(), f.as_str()))
+                .collect();
+            preflight_or_exit(&all_files);
+            exit_on_external_command_error(
+                run_bifrost(genome_fasta_files, rep_fasta_files, m.clone()));
         }
         Some("kmer") => {
             let m = matches.subcommand_matches("kmer").unwrap();
-            let mut genomes_and_contigs;
-            let mut genomes_string = String::new();
-            if m.is_present("fasta-files") {
-                let genome_fasta_files: Vec<&str> = m.values_of("fasta-files").unwrap().collect();
-                genomes_and_contigs = kmer_indexer::read_genome_fasta_files_as_one_genome(&genome_fasta_files);
-                genomes_and_contigs.establish_kmers(m.value_of("k-mer-size").unwrap().parse::<usize>().unwrap());
-//                genomes_and_contigs.get_kmers();
-            } else if m.is_present("fasta-directory") {
-                // println!("{}",format!("{:?}", m.value_of("fasta-directory").unwrap()));
-                let mut file_path = File::open(m.value_of("fasta-directory").unwrap()).unwrap();
-                // println!("{}",format!("{:?}", file_path));
-                let mut contents = String::new();
-                file_path.read_to_string(&mut contents);
-                // println!("{}",format!("{:?}", contents));
-                let mut rdr = csv::ReaderBuilder::new()
-                    .delimiter(b'\t')
-                    .has_headers(false)
-                    .from_reader(contents.as_bytes());
-                let mut genome_fasta_files: Vec<String> = Vec::new();
-                for line in rdr.records() {
-                    let record = line.unwrap();
-                    // println!("{}",format!("{:?}", record));
-                    let file = &record[1];
-                    // let mut s = String::from(file);
-                    let file_split: Vec<&str> = file.split("/").collect();
-                    // println!("{}",format!("{:?}", file_split));
-                    let file_name = format!("{}_genomic.fna", file_split.last().unwrap());
-                    // println!("{}",format!("{:?}", file_name));
-                    genome_fasta_files.push([file.to_string(), file_name].join("/"));
-
-                    let mut strs: Vec<&str> = vec!();
-                    for f in &genome_fasta_files {
-                        strs.push(f);
+            set_log_level(m);
+            let kmer_size = value_t!(m.value_of("k-mer-size"), usize).unwrap_or_else(|e| e.exit());
+            if kmer_size == 0 || kmer_size > u128::MAX_K {
+                error!("k-mer size must be between 1 and {}", u128::MAX_K);
+                process::exit(1);
+            }
+            let canonical = !m.is_present("forward-strand-only");
+            let threads = value_t!(m.value_of("threads"), usize).unwrap_or_else(|e| e.exit());
+            let genome_files = genome_files(m, str::to_string);
+            let genome_files = as_str_pairs(&genome_files);
+            preflight_or_exit(&genome_files);
+            count_and_write_kmers(&genome_files, kmer_size, canonical, threads, m);
+        }
+        Some("similarity") => {
+            let m = matches.subcommand_matches("similarity").unwrap();
+            set_log_level(m);
+            let kmer_size = value_t!(m.value_of("k-mer-size"), usize).unwrap_or_else(|e| e.exit());
+            if kmer_size == 0 || kmer_size > u128::MAX_K {
+                error!("k-mer size must be between 1 and {}", u128::MAX_K);
+                process::exit(1);
+            }
+            let canonical = !m.is_present("forward-strand-only");
+            let genome_files = genome_files(m, kmer_indexer::genome_name_from_path);
+            let genome_files = as_str_pairs(&genome_files);
+            preflight_or_exit(&genome_files);
+            let genomes_and_contigs = kmer_indexer::read_named_genome_fasta_files(&genome_files)
+                .unwrap_or_else(|e| {
+                    error!("Failed to read genomes: {}", e);
+                    process::exit(1);
+                });
+            let similarities = if kmer_size <= u64::MAX_K {
+                Similarities::from_kmer_map(
+                    &genomes_and_contigs.establish_kmers::<u64>(kmer_size, canonical))
+            } else {
+                Similarities::from_kmer_map(
+                    &genomes_and_contigs.establish_kmers::<u128>(kmer_size, canonical))
+            };
+            let output_directory = m.value_of("output").unwrap();
+            if let Err(e) = similarities.write_all(output_directory) {
+                error!("Failed to write similarities to {}: {}", output_directory, e);
+                process::exit(1);
+            }
+        }
+        Some("sketch") => {
+            let m = matches.subcommand_matches("sketch").unwrap();
+            set_log_level(m);
+            let kmer_size = value_t!(m.value_of("k-mer-size"), usize).unwrap_or_else(|e| e.exit());
+            if kmer_size == 0 || kmer_size > u128::MAX_K {
+                error!("k-mer size must be between 1 and {}", u128::MAX_K);
+                process::exit(1);
+            }
+            let kind = if m.is_present("scaled") {
+                SketchKind::Scaled(value_t!(m.value_of("scaled"), u64).unwrap_or_else(|e| e.exit()))
+            } else {
+                SketchKind::BottomK(value_t!(m.value_of("sketch-size"), u64).unwrap_or_else(|e| e.exit()))
+            };
+            if kind == SketchKind::BottomK(0) || kind == SketchKind::Scaled(0) {
+                error!("Sketch size and scale must be at least 1");
+                process::exit(1);
+            }
+            let parameters = SketchParameters {
+                kmer_size,
+                canonical: !m.is_present("forward-strand-only"),
+                seed: value_t!(m.value_of("seed"), u64).unwrap_or_else(|e| e.exit()),
+                kind,
+            };
+            let genome_files = genome_files(m, kmer_indexer::genome_name_from_path);
+            let genome_files = as_str_pairs(&genome_files);
+            preflight_or_exit(&genome_files);
+            let sketches = sketch::sketch_genome_fasta_files(&genome_files, parameters)
+                .unwrap_or_else(|e| {
+                    error!("Failed to sketch genomes: {}", e);
+                    process::exit(1);
+                });
+            let output = m.value_of("output").unwrap();
+            if let Err(e) = File::create(output).and_then(|f| sketch::write_sketches(&sketches, f)) {
+                error!("Failed to write sketches to {}: {}", output, e);
+                process::exit(1);
+            }
+            info!("Wrote {} sketches to {}", sketches.len(), output);
+        }
+        Some("compare-sketches") => {
+            let m = matches.subcommand_matches("compare-sketches").unwrap();
+            set_log_level(m);
+            let mut sketches = vec!();
+            for path in m.values_of("sketches").unwrap() {
+                match sketch::read_sketch_file(path) {
+                    Ok(mut read) => sketches.append(&mut read),
+                    Err(e) => {
+                        error!("Failed to read sketches from {}: {}", path, e);
+                        process::exit(1);
                     }
-                    // info!("{:?}", &strs);
-                    genomes_and_contigs = kmer_indexer::read_genome_fasta_files_as_one_genome(&strs);
-                    genomes_and_contigs.establish_kmers(m.value_of("k-mer-size").unwrap().parse::<usize>().unwrap());
-//                    genomes_and_contigs.get_kmers();
                 }
             }
+            if let Err(e) = open_output(m).and_then(|output| write_sketch_comparisons(&sketches, output)) {
+                error!("Failed to compare sketches: {}", e);
+                process::exit(1);
+            }
+        }
+        Some("quant") => {
+            let m = matches.subcommand_matches("quant").unwrap();
+            set_log_level(m);
+            exit_on_external_command_error(run_kallisto_quant(m));
+        }
+        Some("stats") => {
+            let m = matches.subcommand_matches("stats").unwrap();
+            set_log_level(m);
+            let genome_files = genome_files(m, str::to_string);
+            let genome_files = as_str_pairs(&genome_files);
+            preflight_or_exit(&genome_files);
+            write_assembly_stats(&genome_files, m).unwrap_or_else(|e| {
+                error!("Failed to write assembly statistics: {}", e);
+                process::exit(1);
+            });
+        }
+        Some("validate") => {
+            let m = matches.subcommand_matches("validate").unwrap();
+            set_log_level(m);
+            let genome_files = genome_files(m, str::to_string);
+            let strs: Vec<&str> = genome_files.iter().map(|(_, path)| path.as_str()).collect();
+            let problems = validation::validate_genome_files(&strs);
+            validation::write_summary(&problems, strs.len(), io::stdout()).unwrap();
+            if !problems.is_empty() {
+                process::exit(1);
+            }
         }
             _ => {
             app.print_help().unwrap();
@@ -189,85 +218,634 @@ fn main() {
         }
     }
 
-fn run_bifrost(matches: ArgMatches) -> Result<()>{
+fn run_bifrost(genome_fasta_files: Vec<String>, rep_fasta_files: Vec<String>, matches: ArgMatches)
+    -> std::result::Result<(), ExternalCommandError>{
     let m = matches;
-    let mkdir = Command::new("mkdir")
-                .arg(format!("{}", m.value_of("output").unwrap()))
-                .output()
-                .expect("Failed");
-    println!("stderr mkdir: {}", String::from_utf8(mkdir.stderr).unwrap());
-    let mut output;
+    let output_directory = m.value_of("output").unwrap();
+    let mut builder = match m.value_of("update-graph") {
+        Some(graph_file) => {
+            let builder = BifrostBuilder::update(output_directory, graph_file);
+            match m.value_of("colors-file") {
+                Some(colors_file) => builder.colors_file(colors_file),
+                None => builder
+            }
+        },
+        None => BifrostBuilder::build(output_directory)
+    };
+    builder = builder
+        .sequence_genomes(genome_fasta_files)
+        .reference_genomes(rep_fasta_files)
+        .threads(value_t!(m.value_of("threads"), usize).unwrap_or_else(|e| e.exit()))
+        .colors(true)
+        .verbose(true);
     if m.is_present("k-mer-size"){
-        output = Command::new("Bifrost")
-                .arg("build")
-                .arg("-s genome_list.txt")
-                .arg("-r rep_genome_list.txt")
-                .arg(format!("-o {}/bifrost_graph", m.value_of("output").unwrap()))
-                .arg(format!("--kmer-length {}", m.value_of("k-mer-size").unwrap()))
-                .arg(format!("-t {}", m.value_of("threads").unwrap()))
-                .arg("-c")
-                .arg("-v")
-                .output()
-                .expect("failed to execute process");
-    }else{
-        
-        output = Command::new("Bifrost")
-                .arg("build")
-                .arg("-s genome_list.txt")
-                .arg("-r rep_genome_list.txt")
-                .arg(format!("-o {}/bifrost_graph", m.value_of("output").unwrap()))
-                .arg(format!("-t {}", m.value_of("threads").unwrap()))
-                .arg("-c")
-                .arg("-v")
-                .output()
-                .expect("failed to execute process");
+        builder = builder.kmer_length(value_t!(m.value_of("k-mer-size"), usize).unwrap_or_else(|e| e.exit()));
+    }
+    if m.is_present("minimizer-length"){
+        builder = builder.minimizer_length(
+            value_t!(m.value_of("minimizer-length"), usize).unwrap_or_else(|e| e.exit()));
     }
+    let output = builder.run()?;
+    info!("Bifrost stdout: {}", String::from_utf8_lossy(&output.stdout));
     Ok(())
 
 }
 
-fn run_kallisto(genomes_string: String, matches: ArgMatches) -> Result<()>{
+fn run_kallisto(genome_files: &[(&str, &str)], matches: ArgMatches) -> std::result::Result<(), ExternalCommandError>{
         let m = matches;
-        let mkdir = Command::new("mkdir")
-                .arg(format!("{}", m.value_of("output").unwrap()))
-                .output()
-                .expect("Failed");
-        println!("stderr mkdir: {}", String::from_utf8(mkdir.stderr).unwrap());
+        fs::create_dir_all(m.value_of("output").unwrap())?;
         let dir = Builder::new().tempdir_in(format!("{}", m.value_of("output").unwrap()))?;
         // info!("Temp Path {:?}", dir.unwrap().path());
         let temp_file_path = dir.path().join("temp_genome.fasta");
-        let mut temp_file = File::create(temp_file_path.clone())?;
-        let mut output;
-        // println!("{}", &genomes_string);
-        temp_file.write_all(genomes_string.as_bytes())?;
-        let touch = Command::new("touch")
-                .arg(format!("{}/genomes.idx", m.value_of("output").unwrap()))
-                .output()
-                .expect("Failed");
-        println!("stderr touch: {}", String::from_utf8(touch.stderr).unwrap());
+        let mut command = Command::new("kallisto");
+        command.arg("index")
+                .arg(format!("--index={}/{}", m.value_of("output").unwrap(), quant::INDEX_FILE_NAME));
         if m.is_present("k-mer-size"){
-            output = Command::new("kallisto")
-                    .arg("index")
-                    .arg(format!("--index={}/genomes.idx", m.value_of("output").unwrap()))
-                    .arg(format!("--kmer-size={}", m.value_of("k-mer-size").unwrap()))
-                    .arg(temp_file_path)
-                    .output()
-                    .expect("failed to execute process");
-        }else{
-            
-            output = Command::new("kallisto")
-                    .arg("index")
-                    .arg(format!("--index={}/genomes.idx", m.value_of("output").unwrap()))
-                    .arg(temp_file_path)
-                    .output()
-                    .expect("failed to execute process");
+            command.arg(format!("--kmer-size={}", m.value_of("k-mer-size").unwrap()));
         }
-        println!("stdout: {}", String::from_utf8_lossy(&output.stdout));
-        println!("stdout: {}", String::from_utf8_lossy(&output.stderr));
+        command.arg(&temp_file_path);
+
+        let naming = TargetNaming::from_name(m.value_of("target-naming").unwrap()).unwrap();
+        let targets_to_genomes_path = Path::new(m.value_of("output").unwrap())
+                .join(targets::TARGETS_TO_GENOMES_FILE_NAME);
+        let targets_to_genomes = File::create(&targets_to_genomes_path)?;
+
+        let output;
+        if m.is_present("named-pipe") {
+            // kallisto reads the genomes from a FIFO while they are written,
+            // so they never touch the disk.
+            mkfifo(&temp_file_path, stat::Mode::S_IRWXU)
+                .map_err(io::Error::other)?;
+            let child = external_command::spawn_command(
+                command
+                    .stdout(process::Stdio::piped())
+                    .stderr(process::Stdio::piped()),
+                "kallisto")?;
+            let fifo_path = temp_file_path.clone();
+            let files: Vec<(String, String)> = genome_files.iter()
+                .map(|&(name, path)| (name.to_string(), path.to_string()))
+                .collect();
+            let writer_thread = thread::spawn(move || -> io::Result<usize> {
+                let mut writer = kmer_indexer::Writer::new(File::create(&fifo_path)?);
+                let mut namer = TargetNamer::new(naming, targets_to_genomes)?;
+                kmer_indexer::write_genome_fasta_files(&as_str_pairs(&files), &mut writer, &mut namer)
+            });
+            let waited = child.wait_with_output();
+            // If kallisto exited without opening the pipe, the writer is
+            // still blocked waiting for a reader, so open one to release it.
+            let _ = fs::OpenOptions::new()
+                    .read(true)
+                    .custom_flags(fcntl::OFlag::O_NONBLOCK.bits())
+                    .open(&temp_file_path);
+            let streamed = writer_thread.join();
+            output = external_command::check_output("kallisto", waited?)?;
+            match streamed {
+                Ok(Ok(num_records)) => info!("Streamed {} records to kallisto", num_records),
+                Ok(Err(e)) => return Err(ExternalCommandError::Io(e)),
+                Err(_) => return Err(ExternalCommandError::Io(io::Error::other(
+                    "Failed to stream genomes to kallisto"))),
+            }
+        } else {
+            {
+                let mut writer = kmer_indexer::Writer::to_file(&temp_file_path)?;
+                let mut namer = TargetNamer::new(naming, targets_to_genomes)?;
+                kmer_indexer::write_genome_fasta_files(genome_files, &mut writer, &mut namer)?;
+            }
+            output = external_command::run_command(&mut command, "kallisto")?;
+        }
+        info!("kallisto stdout: {}", String::from_utf8_lossy(&output.stdout));
+        info!("kallisto stderr: {}", String::from_utf8_lossy(&output.stderr));
         // drop(temp_file);
         Ok(dir.close()?)
     }
 
+/// Run kallisto quant against the index in --index-directory, then sum the
+/// abundance of each genome's targets into genome_abundance.tsv in the
+/// output directory.
+fn run_kallisto_quant(m: &ArgMatches) -> std::result::Result<(), ExternalCommandError> {
+    let index_directory = Path::new(m.value_of("index-directory").unwrap());
+    let output_directory = m.value_of("output").unwrap();
+    let paths = |arg| -> Vec<PathBuf> {
+        m.values_of(arg).map(|values| values.map(PathBuf::from).collect()).unwrap_or_default()
+    };
+    let reads = if m.is_present("single") {
+        QuantReads::Single {
+            files: paths("single"),
+            fragment_length: value_t!(m.value_of("fragment-length"), f64).unwrap_or_else(|e| e.exit()),
+            fragment_sd: value_t!(m.value_of("fragment-sd"), f64).unwrap_or_else(|e| e.exit()),
+        }
+    } else {
+        let (forward, reverse) = (paths("forward"), paths("reverse"));
+        if forward.len() != reverse.len() {
+            error!("Found {} forward but {} reverse read files", forward.len(), reverse.len());
+            process::exit(1);
+        }
+        QuantReads::Paired(forward.into_iter().zip(reverse).collect())
+    };
+    let mut builder = KallistoQuantBuilder::new(
+        index_directory.join(quant::INDEX_FILE_NAME), output_directory, reads)
+        .threads(value_t!(m.value_of("threads"), usize).unwrap_or_else(|e| e.exit()));
+    if let Some(fragment_length) = optional_value(m, "fragment-length") {
+        builder = builder.fragment_length(fragment_length);
+    }
+    if let Some(fragment_sd) = optional_value(m, "fragment-sd") {
+        builder = builder.fragment_sd(fragment_sd);
+    }
+    let output = builder.run()?;
+    info!("kallisto stderr: {}", String::from_utf8_lossy(&output.stderr));
+
+    let targets_to_genomes = targets::read_targets_to_genomes(
+        index_directory.join(targets::TARGETS_TO_GENOMES_FILE_NAME))?;
+    let abundances = quant::read_abundance(builder.abundance_path())?;
+    let genome_abundances = quant::abundance_by_genome(&abundances, &targets_to_genomes)?;
+    let genome_abundance_path = Path::new(output_directory).join(quant::GENOME_ABUNDANCE_FILE_NAME);
+    quant::write_genome_abundances(&genome_abundances, File::create(&genome_abundance_path)?)?;
+    info!("Wrote abundances of {} genomes to {}", genome_abundances.len(),
+          genome_abundance_path.display());
+    Ok(())
+}
+
+/// Count k-mers in the smallest integer type which holds them, then write
+/// the table and report the windows counted and skipped. With --max-memory
+/// the counts are partitioned on disk rather than held in memory.
+fn count_and_write_kmers(genome_files: &[(&str, &str)], kmer_size: usize,
+                         canonical: bool, threads: usize, m: &ArgMatches) {
+    if let Some(max_memory) = m.value_of("max-memory") {
+        let max_memory = partitioned::parse_memory_size(max_memory).unwrap_or_else(|| {
+            error!("Could not parse memory size {}", max_memory);
+            process::exit(1);
+        });
+        let result = count_and_write_kmers_partitioned(
+            genome_files, kmer_size, canonical, max_memory, m);
+        match result {
+            Ok(window_counts) => {
+                let genomes: Vec<String> = genome_files.iter().map(|&(name, _)| name.to_string()).collect();
+                report_window_counts(&genomes, &window_counts, m);
+            },
+            Err(e) => {
+                error!("Failed to count k-mers: {}", e);
+                process::exit(1);
+            }
+        }
+        return
+    }
+    let genomes_and_contigs = kmer_indexer::read_named_genome_fasta_files_in_threads(
+        genome_files, threads).unwrap_or_else(|e| {
+            error!("Failed to read genomes: {}", e);
+            process::exit(1);
+        });
+    if kmer_size <= u64::MAX_K {
+        let kmer_map = genomes_and_contigs.establish_kmers_in_threads::<u64>(
+            kmer_size, canonical, threads);
+        write_kmer_map(&kmer_map, m);
+    } else {
+        let kmer_map = genomes_and_contigs.establish_kmers_in_threads::<u128>(
+            kmer_size, canonical, threads);
+        write_kmer_map(&kmer_map, m);
+    }
+}
+
+fn count_and_write_kmers_partitioned(genome_files: &[(&str, &str)], kmer_size: usize, canonical: bool,
+                                    max_memory: usize, m: &ArgMatches) -> io::Result<Vec<WindowCounts>> {
+    let dir = match m.value_of("temp-directory") {
+        Some(temp_directory) => Builder::new().prefix("kmer_partitions").tempdir_in(temp_directory)?,
+        None => Builder::new().prefix("kmer_partitions").tempdir()?,
+    };
+    let counter = PartitionedCounter::new(kmer_size, canonical, max_memory, dir.path());
+    let bytes_per_kmer = if kmer_size <= u64::MAX_K {
+        counter.bytes_per_kmer::<u64>(genome_files.len())
+    } else {
+        counter.bytes_per_kmer::<u128>(genome_files.len())
+    };
+    if bytes_per_kmer > max_memory {
+        warn!("--max-memory of {} bytes is less than the {} bytes needed to merge the counts \
+               of one k-mer across {} genomes, so it will be exceeded",
+              max_memory, bytes_per_kmer, genome_files.len());
+    }
+    let mut writer = open_kmer_table_writer(m)?;
+    let window_counts = if kmer_size <= u64::MAX_K {
+        counter.count::<u64>(genome_files, &mut *writer)?
+    } else {
+        counter.count::<u128>(genome_files, &mut *writer)?
+    };
+    dir.close()?;
+    Ok(window_counts)
+}
+
+fn write_kmer_map<T: Kmer>(kmer_map: &KmerMap<T>, m: &ArgMatches) {
+    report_window_counts(&kmer_map.genomes, &kmer_map.window_counts, m);
+    let result = if m.is_present("unique") {
+        let max_other_genomes = value_t!(m.value_of("max-other-genomes"), usize)
+            .unwrap_or_else(|e| e.exit());
+        open_output(m).and_then(|output| kmer_map.write_unique_kmers(max_other_genomes, output))
+    } else {
+        open_kmer_table_writer(m).and_then(|mut writer| kmer_map.write_table(&mut *writer))
+    };
+    if let Err(e) = result {
+        error!("Failed to write k-mer table: {}", e);
+        process::exit(1);
+    }
+}
+
+/// Open a writer in the format given by --output-format, writing to the
+/// file given by --output or to stdout. Matrix Market row labels are written
+/// next to the output file with a .kmers suffix.
+fn open_kmer_table_writer(m: &ArgMatches) -> io::Result<Box<dyn KmerTableWriter>> {
+    let format = KmerTableFormat::from_name(m.value_of("output-format").unwrap()).unwrap();
+    let output = open_output(m)?;
+    Ok(match format {
+        KmerTableFormat::Tsv => Box::new(kmer_table::TsvWriter::new(output)),
+        KmerTableFormat::Binary => Box::new(kmer_table::BinaryWriter::new(output)),
+        KmerTableFormat::MatrixMarket => {
+            let labels_path = match m.value_of("output") {
+                Some(path) => format!("{}.kmers", path),
+                None => return Err(io::Error::new(
+                    io::ErrorKind::InvalidInput,
+                    "An --output file is required for matrix-market output")),
+            };
+            Box::new(kmer_table::MatrixMarketWriter::new(output, File::create(labels_path)?)?)
+        },
+    })
+}
+
+/// Compare each ordered pair of distinct sketches, writing one row per pair.
+fn write_sketch_comparisons<W: Write>(sketches: &[sketch::Sketch], writer: W) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(writer);
+    writeln!(writer, "query\treference\tjaccard\tcontainment\tmash_distance\tani")?;
+    for (i, query) in sketches.iter().enumerate() {
+        for (j, reference) in sketches.iter().enumerate() {
+            if i == j {
+                continue
+            }
+            let comparison = query.compare(reference)?;
+            writeln!(writer, "{}\t{}\t{}\t{}\t{}\t{}", query.name, reference.name,
+                     comparison.jaccard, comparison.containment,
+                     comparison.mash_distance, comparison.ani)?;
+        }
+    }
+    writer.flush()
+}
+
+/// Read each genome file in turn and write the statistics of its assembly
+/// to --output, and of its contigs to --contig-output if given.
+fn write_assembly_stats(genome_files: &[(&str, &str)], m: &ArgMatches) -> io::Result<()> {
+    let format = StatsFormat::from_name(m.value_of("output-format").unwrap()).unwrap();
+    let mut genome_stats = vec!();
+    let mut contig_stats = vec!();
+    for genome_file in genome_files {
+        let genome = kmer_indexer::read_named_genome_fasta_files(&[*genome_file])?;
+        genome_stats.push(stats::genome_stats(&genome, 0));
+        if m.is_present("contig-output") {
+            contig_stats.extend(stats::contig_stats(&genome, 0));
+        }
+    }
+    stats::write_genome_stats(&genome_stats, format, open_output(m)?)?;
+    if let Some(path) = m.value_of("contig-output") {
+        stats::write_contig_stats(&contig_stats, format, File::create(path)?)?;
+    }
+    Ok(())
+}
+
+/// Read the manifest given by --fasta-directory, resolving paths by the rule
+/// given by --path-resolution and keeping the genomes which pass any taxon
+/// and quality filters, and exit if it is malformed.
+fn read_manifest_or_exit(m: &ArgMatches) -> Vec<GenomeManifestEntry> {
+    let resolution = PathResolution::from_name(m.value_of("path-resolution").unwrap()).unwrap();
+    let path = m.value_of("fasta-directory").unwrap();
+    let entries = manifest::read_manifest(path, resolution).unwrap_or_else(|e| {
+        error!("Failed to read manifest: {}", e);
+        process::exit(1);
+    });
+    filter_by_quality_or_exit(filter_by_taxon_or_exit(entries, m), m)
+}
+
+/// Keep the manifest entries within the thresholds given by
+/// --min-completeness, --max-contamination, --max-contigs and --min-n50,
+/// judged by the file given by --quality. Dropped genomes are logged, and
+/// written to --quality-report if given.
+fn filter_by_quality_or_exit(entries: Vec<GenomeManifestEntry>, m: &ArgMatches) -> Vec<GenomeManifestEntry> {
+    let path = match m.value_of("quality") {
+        Some(path) => path,
+        None => return entries,
+    };
+    let thresholds = QualityThresholds {
+        min_completeness: optional_value(m, "min-completeness"),
+        max_contamination: optional_value(m, "max-contamination"),
+        max_contigs: optional_value(m, "max-contigs"),
+        min_n50: optional_value(m, "min-n50"),
+    };
+    let table = quality::read_quality(path).unwrap_or_else(|e| {
+        error!("Failed to read quality file: {}", e);
+        process::exit(1);
+    });
+    let num_entries = entries.len();
+    let (kept, dropped) = table.filter(entries, &thresholds);
+    for genome in &dropped {
+        info!("Dropping {}: {}", genome.entry.accession, genome.reasons.join("; "));
+    }
+    if let Some(report) = m.value_of("quality-report") {
+        File::create(report).and_then(|f| quality::write_dropped_report(&dropped, f))
+            .unwrap_or_else(|e| {
+                error!("Failed to write quality report {}: {}", report, e);
+                process::exit(1);
+            });
+    }
+    info!("Kept {} of {} genomes after quality filtering", kept.len(), num_entries);
+    if kept.is_empty() {
+        error!("No genomes are left after quality filtering");
+        process::exit(1);
+    }
+    kept
+}
+
+/// Parse an optional numeric argument, exiting if it is invalid.
+fn optional_value<T: std::str::FromStr>(m: &ArgMatches, name: &str) -> Option<T> {
+    m.value_of(name).map(|_| value_t!(m.value_of(name), T).unwrap_or_else(|e| e.exit()))
+}
+
+/// Keep the manifest entries whose lineage passes --include-taxon and
+/// --exclude-taxon. Lineages come from the GTDB file given by --taxonomy, or
+/// else the taxonomy column of the manifest. Genomes without a lineage are
+/// dropped only if taxa to include are given.
+fn filter_by_taxon_or_exit(entries: Vec<GenomeManifestEntry>, m: &ArgMatches) -> Vec<GenomeManifestEntry> {
+    let taxa = |arg| m.values_of(arg)
+        .map(|values| values.map(|v| v.to_string()).collect())
+        .unwrap_or_default();
+    let filter = TaxonFilter { include: taxa("include-taxon"), exclude: taxa("exclude-taxon") };
+    if filter.is_empty() {
+        return entries
+    }
+    let tree = m.value_of("taxonomy").map(|path| {
+        taxonomy::read_taxonomy(path).unwrap_or_else(|e| {
+            error!("Failed to read taxonomy: {}", e);
+            process::exit(1);
+        })
+    });
+    if let Some(ref tree) = tree {
+        for taxon in filter.include.iter().chain(filter.exclude.iter()) {
+            if !tree.contains_taxon(taxon) {
+                warn!("Taxon {} is not in the taxonomy", taxon);
+            }
+        }
+    }
+
+    let num_entries = entries.len();
+    let mut num_without_lineage = 0;
+    let kept: Vec<GenomeManifestEntry> = entries.into_iter().filter(|entry| {
+        let lineage = match tree {
+            Some(ref tree) => tree.lineage(&entry.accession),
+            None => entry.taxonomy.as_ref().map(|t| taxonomy::parse_lineage(t)),
+        };
+        match lineage {
+            Some(lineage) => filter.matches(&lineage),
+            None => {
+                num_without_lineage += 1;
+                filter.include.is_empty()
+            }
+        }
+    }).collect();
+    if num_without_lineage > 0 {
+        warn!("{} of {} genomes have no taxonomy", num_without_lineage, num_entries);
+    }
+    info!("Kept {} of {} genomes after taxon filtering", kept.len(), num_entries);
+    if kept.is_empty() {
+        error!("No genomes are left after taxon filtering");
+        process::exit(1);
+    }
+    kept
+}
+
+/// Check that every genome file can be read before any work is done,
+/// reporting all problems at once and exiting if there are any.
+fn preflight_or_exit(genome_files: &[(&str, &str)]) {
+    let strs: Vec<&str> = genome_files.iter().map(|&(_, path)| path).collect();
+    let problems = validation::validate_genome_files(&strs);
+    if !problems.is_empty() {
+        error!("{} of {} genome files have problems:", problems.len(), strs.len());
+        for problem in &problems {
+            error!("{}", problem);
+        }
+        process::exit(1);
+    }
+}
+
+/// Return each genome as `(name, path)`. Genome files given by --fasta-files
+/// are named by `default_name`, and genomes listed in the manifest given by
+/// --fasta-directory by their accessions.
+fn genome_files(m: &ArgMatches, default_name: fn(&str) -> String) -> Vec<(String, String)> {
+    match m.values_of("fasta-files") {
+        Some(files) => files.map(|f| (default_name(f), f.to_string())).collect(),
+        None => {
+            let entries = read_manifest_or_exit(m);
+            let entries = match read_representatives_or_exit(m) {
+                Some(clusters) => clusters.select(entries, representative_selection(m)),
+                None => entries,
+            };
+            entries.into_iter().map(GenomeManifestEntry::into_genome_file).collect()
+        }
+    }
+}
+
+/// Borrow genome names and paths as the library takes them.
+fn as_str_pairs(genome_files: &[(String, String)]) -> Vec<(&str, &str)> {
+    genome_files.iter().map(|(name, path)| (name.as_str(), path.as_str())).collect()
+}
+
+/// Read the species clusters given by --representatives, if any, and exit
+/// if the file is malformed.
+fn read_representatives_or_exit(m: &ArgMatches) -> Option<SpeciesClusters> {
+    m.value_of("representatives").map(|path| {
+        representatives::read_representatives(path).unwrap_or_else(|e| {
+            error!("Failed to read representatives: {}", e);
+            process::exit(1);
+        })
+    })
+}
+
+/// The genomes of each species cluster chosen by --selection or
+/// --genomes-per-species, or all of them if neither is given.
+fn representative_selection(m: &ArgMatches) -> RepresentativeSelection {
+    if m.is_present("genomes-per-species") {
+        let n = value_t!(m.value_of("genomes-per-species"), usize).unwrap_or_else(|e| e.exit());
+        RepresentativeSelection::per_species(n).unwrap_or_else(|| {
+            error!("--genomes-per-species must be at least 1");
+            process::exit(1);
+        })
+    } else {
+        m.value_of("selection")
+            .map(|name| RepresentativeSelection::from_name(name).unwrap())
+            .unwrap_or(RepresentativeSelection::All)
+    }
+}
+
+/// Open the file given by --output, or stdout if there is none.
+fn open_output(m: &ArgMatches) -> io::Result<Box<dyn Write>> {
+    Ok(match m.value_of("output") {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(io::stdout()),
+    })
+}
+
+/// Write the per-genome tally of counted and skipped k-mer windows to the
+/// file given by --window-report, or log the skipped windows otherwise.
+fn report_window_counts(genomes: &[String], window_counts: &[WindowCounts], m: &ArgMatches) {
+    match m.value_of("window-report") {
+        Some(path) => {
+            let mut file = File::create(path).unwrap_or_else(|e| {
+                error!("Failed to create window report {}: {}", path, e);
+                process::exit(1);
+            });
+            kmer::write_window_counts(genomes, window_counts, &mut file).unwrap_or_else(|e| {
+                error!("Failed to write window report {}: {}", path, e);
+                process::exit(1);
+            });
+        },
+        None => {
+            for (genome, counts) in genomes.iter().zip(window_counts.iter()) {
+                info!("{}: counted {} k-mers, skipped {} windows containing N, {} \
+                       containing other ambiguous bases and {} spanning contig boundaries",
+                      genome, counts.counted, counts.skipped_n,
+                      counts.skipped_other_ambiguous, counts.skipped_contig_boundary);
+            }
+        }
+    }
+}
+
+/// Log the failure of an external program and exit with a non-zero status.
+fn exit_on_external_command_error(result: std::result::Result<(), ExternalCommandError>) {
+    if let Err(e) = result {
+        error!("{}", e);
+        process::exit(1);
+    }
+}
+
+fn set_log_level(matches: &ArgMatches) {
+    let mut log_level = LogLevelFilter::Info;
+    if matches.is_present("verbose") {
+        log_level = LogLevelFilter::Debug;
+    }
+    if matches.is_present("quiet") {
+        log_level = LogLevelFilter::Error;
+    }
+    let mut builder = LogBuilder::new();
+    builder.filter(None, log_level);
+    if let Ok(rust_log) = env::var("RUST_LOG") {
+        builder.parse(&rust_log);
+    }
+    builder.init().unwrap();
+}
+
+/// Arguments of every subcommand which reads a --fasta-directory manifest.
+fn manifest_args<'a, 'b>() -> Vec<Arg<'a, 'b>> {
+    let rules: Vec<&'static str> = PathResolution::all().iter().map(|r| r.name()).collect();
+    vec![
+        Arg::with_name("path-resolution")
+            .long("path-resolution")
+            .help("How the path column of the --fasta-directory manifest \
+            is turned into a FASTA file: auto tries each rule in turn, \
+            genomic-fna and genomic-fna-gz give <path>/<basename>_genomic.fna(.gz), \
+            fna and fna-gz give <path>.fna(.gz) and direct uses the path as is")
+            .possible_values(&rules)
+            .default_value(PathResolution::Auto.name()),
+    ]
+}
+
+/// Arguments choosing which genomes of a --fasta-directory manifest the
+/// index building subcommands use. Bifrost always needs --representatives,
+/// as they are built into the graph first.
+fn genome_selection_args<'a, 'b>(representatives_required: bool) -> Vec<Arg<'a, 'b>> {
+    vec![
+        Arg::with_name("taxonomy")
+            .long("taxonomy")
+            .help("GTDB taxonomy file giving the lineage of each \
+            genome for --include-taxon and --exclude-taxon, rather \
+            than the taxonomy column of the manifest")
+            .takes_value(true)
+            .required(false)
+            .requires("fasta-directory"),
+        Arg::with_name("include-taxon")
+            .long("include-taxon")
+            .help("Use only genomes within one of these taxa, \
+            e.g. g__Escherichia")
+            .multiple(true)
+            .takes_value(true)
+            .required(false)
+            .requires("fasta-directory"),
+        Arg::with_name("exclude-taxon")
+            .long("exclude-taxon")
+            .help("Leave out genomes within any of these taxa, \
+            e.g. p__Firmicutes")
+            .multiple(true)
+            .takes_value(true)
+            .required(false)
+            .requires("fasta-directory"),
+        Arg::with_name("representatives")
+            .short("r")
+            .long("representatives")
+            .help("TSV with a header of species representatives in \
+            the first column, or GTDB metadata with a \
+            gtdb_genome_representative column")
+            .takes_value(true)
+            .required(representatives_required)
+            .requires("fasta-directory"),
+        Arg::with_name("selection")
+            .long("selection")
+            .help("Use only the species representatives given by \
+            --representatives, or only the other genomes")
+            .possible_values(&representatives::SELECTION_NAMES)
+            .conflicts_with("genomes-per-species")
+            .takes_value(true)
+            .required(false)
+            .requires("representatives"),
+        Arg::with_name("genomes-per-species")
+            .long("genomes-per-species")
+            .help("Use up to this many genomes from each species \
+            cluster, starting with its representative")
+            .takes_value(true)
+            .required(false)
+            .requires("representatives"),
+        Arg::with_name("quality")
+            .long("quality")
+            .help("GTDB metadata, CheckM (qa --tab_table) or CheckM2 \
+            quality report giving the completeness, contamination, \
+            contig count and N50 of each genome of the manifest")
+            .takes_value(true)
+            .required(false)
+            .requires("fasta-directory"),
+        Arg::with_name("min-completeness")
+            .long("min-completeness")
+            .help("Leave out genomes less complete than this percentage")
+            .requires("quality")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("max-contamination")
+            .long("max-contamination")
+            .help("Leave out genomes more contaminated than this percentage")
+            .requires("quality")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("max-contigs")
+            .long("max-contigs")
+            .help("Leave out genomes in more than this many contigs")
+            .requires("quality")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("min-n50")
+            .long("min-n50")
+            .help("Leave out genomes with a contig N50 below this")
+            .requires("quality")
+            .takes_value(true)
+            .required(false),
+        Arg::with_name("quality-report")
+            .long("quality-report")
+            .help("Write the genomes left out by quality, and why, \
+            to this TSV file")
+            .requires("quality")
+            .takes_value(true)
+            .required(false),
+    ]
+}
+
 fn build_cli() -> App<'static, 'static> {
 
     return App::new("kmer_indexer")
@@ -294,16 +872,30 @@ fn build_cli() -> App<'static, 'static> {
                         .long("fasta-directory")
                         .takes_value(true)
                         .required(true))
-                .arg(Arg::with_name("representatives")
-                        .short("r")
-                        .long("representatives")
-                        .takes_value(true)
-                        .required(true))
+                .args(&manifest_args())
+                .args(&genome_selection_args(true))
                 .arg(Arg::with_name("k-mer-size")
                         .short("k")
                         .long("k-mer-size")
                         .takes_value(true)
                         .required(false))
+                .arg(Arg::with_name("minimizer-length")
+                        .short("m")
+                        .long("minimizer-length")
+                        .takes_value(true)
+                        .required(false))
+                .arg(Arg::with_name("update-graph")
+                        .long("update-graph")
+                        .help("Add the genomes to this existing graph (GFA) \
+                        instead of building a new one")
+                        .takes_value(true)
+                        .required(false))
+                .arg(Arg::with_name("colors-file")
+                        .long("colors-file")
+                        .help("Colours file of the graph given to --update-graph")
+                        .requires("update-graph")
+                        .takes_value(true)
+                        .required(false))
                 .arg(Arg::with_name("output")
                         .short("o")
                         .long("output")
@@ -337,6 +929,8 @@ fn build_cli() -> App<'static, 'static> {
                         .conflicts_with("fasta-files")
                         .takes_value(true)
                         .required_unless("fasta-files"))
+                .args(&manifest_args())
+                .args(&genome_selection_args(false))
                 .arg(Arg::with_name("k-mer-size")
                         .short("k")
                         .long("k-mer-size")
@@ -347,6 +941,22 @@ fn build_cli() -> App<'static, 'static> {
                         .long("output")
                         .takes_value(true)
                         .required(true))
+                .arg(Arg::with_name("target-naming")
+                        .long("target-naming")
+                        .help("How contigs are named in the index: as in their \
+                        FASTA file, prefixed with their genome as \
+                        <genome>|<contig>, or numbered. The genome of each \
+                        target is written to targets_to_genomes.tsv in the \
+                        output directory")
+                        .possible_values(&["contig", "genome-contig", "numeric"])
+                        .default_value("genome-contig")
+                        .takes_value(true)
+                        .required(false))
+                .arg(Arg::with_name("named-pipe")
+                        .long("named-pipe")
+                        .help("Stream genomes to kallisto through a named pipe rather \
+                        than a temporary file. Requires a kallisto version which \
+                        reads its input in a single pass"))
                 .arg(Arg::with_name("verbose")
                         .short("v")
                         .long("verbose"))
@@ -367,6 +977,7 @@ fn build_cli() -> App<'static, 'static> {
                 .arg(Arg::with_name("threads")
                     .short("-t")
                     .long("threads")
+                    .help("Number of threads reading and counting genomes")
                     .default_value("1")
                     .takes_value(true)
                     .required(false))
@@ -376,12 +987,319 @@ fn build_cli() -> App<'static, 'static> {
                     .conflicts_with("fasta-files")
                     .takes_value(true)
                     .required_unless("fasta-files"))
+                .args(&manifest_args())
+                .args(&genome_selection_args(false))
+                .arg(Arg::with_name("k-mer-size")
+                    .short("k")
+                    .long("k-mer-size")
+                    .help("Length of k-mers, up to 64")
+                    .default_value("31")
+                    .takes_value(true)
+                    .required(false))
+                .arg(Arg::with_name("forward-strand-only")
+                    .long("forward-strand-only")
+                    .help("Count k-mers as they appear on the forward strand \
+                    rather than together with their reverse complement"))
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .help("Write the k-mer table to this file rather than stdout")
+                    .takes_value(true)
+                    .required(false))
+                .arg(Arg::with_name("output-format")
+                    .long("output-format")
+                    .help("Format of the k-mer table. Matrix Market output \
+                    lists non-zero counts only, with the k-mer of each row \
+                    written to <output>.kmers")
+                    .possible_values(&["tsv", "matrix-market", "binary"])
+                    .default_value("tsv"))
+                .arg(Arg::with_name("unique")
+                    .long("unique")
+                    .help("Rather than the table of counts, write the k-mers \
+                    of each genome which are absent from all other genomes, \
+                    as TSV with their count in that genome"))
+                .arg(Arg::with_name("max-other-genomes")
+                    .long("max-other-genomes")
+                    .help("With --unique, also write k-mers found in up to \
+                    this many other genomes")
+                    .default_value("0"))
+                .arg(Arg::with_name("max-memory")
+                    .long("max-memory")
+                    .help("Count k-mers in partitions on disk, merging them \
+                    within about this much memory, e.g. 16G. Genomes are read \
+                    one at a time, so --threads has no effect")
+                    .conflicts_with("unique")
+                    .takes_value(true)
+                    .required(false))
+                .arg(Arg::with_name("temp-directory")
+                    .long("temp-directory")
+                    .help("Directory in which to write partitions with --max-memory")
+                    .takes_value(true)
+                    .required(false))
+                .arg(Arg::with_name("window-report")
+                    .long("window-report")
+                    .help("Write the number of k-mer windows counted in each \
+                    genome, and skipped for containing ambiguous bases or \
+                    spanning contigs, to this file")
+                    .takes_value(true)
+                    .required(false))
+                .arg(Arg::with_name("verbose")
+                    .short("v")
+                    .long("verbose"))
+                .arg(Arg::with_name("quiet")
+                    .short("q")
+                    .long("quiet")))
+        .subcommand(
+            SubCommand::with_name("similarity")
+                .about("Calculate the pairwise Jaccard, containment and \
+                weighted k-mer similarity of genomes")
+                .arg(Arg::with_name("fasta-files")
+                    .short("f")
+                    .long("fasta-files")
+                    .conflicts_with("fasta-directory")
+                    .multiple(true)
+                    .takes_value(true)
+                    .required_unless("fasta-directory"))
+                .arg(Arg::with_name("fasta-directory")
+                    .short("d")
+                    .long("fasta-directory")
+                    .help("Manifest of genomes, with a tab-separated accession \
+                    and path column")
+                    .conflicts_with("fasta-files")
+                    .takes_value(true)
+                    .required_unless("fasta-files"))
+                .args(&manifest_args())
                 .arg(Arg::with_name("k-mer-size")
                     .short("k")
                     .long("k-mer-size")
+                    .help("Length of k-mers, up to 64")
                     .default_value("31")
                     .takes_value(true)
                     .required(false))
+                .arg(Arg::with_name("forward-strand-only")
+                    .long("forward-strand-only")
+                    .help("Compare k-mers as they appear on the forward strand \
+                    rather than together with their reverse complement"))
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .help("Directory to write a jaccard.tsv, containment.tsv \
+                    and weighted.tsv matrix and the long-form similarity.tsv into")
+                    .takes_value(true)
+                    .required(true))
+                .arg(Arg::with_name("verbose")
+                    .short("v")
+                    .long("verbose"))
+                .arg(Arg::with_name("quiet")
+                    .short("q")
+                    .long("quiet")))
+        .subcommand(
+            SubCommand::with_name("sketch")
+                .about("Build MinHash or FracMinHash sketches of genomes")
+                .arg(Arg::with_name("fasta-files")
+                    .short("f")
+                    .long("fasta-files")
+                    .conflicts_with("fasta-directory")
+                    .multiple(true)
+                    .takes_value(true)
+                    .required_unless("fasta-directory"))
+                .arg(Arg::with_name("fasta-directory")
+                    .short("d")
+                    .long("fasta-directory")
+                    .help("Manifest of genomes, with a tab-separated accession \
+                    and path column")
+                    .conflicts_with("fasta-files")
+                    .takes_value(true)
+                    .required_unless("fasta-files"))
+                .args(&manifest_args())
+                .arg(Arg::with_name("k-mer-size")
+                    .short("k")
+                    .long("k-mer-size")
+                    .help("Length of k-mers, up to 64")
+                    .default_value("21")
+                    .takes_value(true)
+                    .required(false))
+                .arg(Arg::with_name("sketch-size")
+                    .short("s")
+                    .long("sketch-size")
+                    .help("Number of smallest hashes kept in each bottom-k sketch")
+                    .default_value("1000")
+                    .takes_value(true))
+                .arg(Arg::with_name("scaled")
+                    .long("scaled")
+                    .help("Build FracMinHash sketches keeping about one in \
+                    this many k-mers, rather than bottom-k sketches")
+                    .takes_value(true)
+                    .required(false))
+                .arg(Arg::with_name("seed")
+                    .long("seed")
+                    .default_value("42")
+                    .takes_value(true))
+                .arg(Arg::with_name("forward-strand-only")
+                    .long("forward-strand-only")
+                    .help("Hash k-mers as they appear on the forward strand \
+                    rather than together with their reverse complement"))
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .help("Sketch file to write")
+                    .takes_value(true)
+                    .required(true))
+                .arg(Arg::with_name("verbose")
+                    .short("v")
+                    .long("verbose"))
+                .arg(Arg::with_name("quiet")
+                    .short("q")
+                    .long("quiet")))
+        .subcommand(
+            SubCommand::with_name("compare-sketches")
+                .about("Estimate the Jaccard similarity, containment and \
+                Mash distance between each pair of sketched genomes")
+                .arg(Arg::with_name("sketches")
+                    .short("s")
+                    .long("sketches")
+                    .help("Sketch files, made with the same parameters")
+                    .multiple(true)
+                    .takes_value(true)
+                    .required(true))
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .help("Write the comparisons to this file rather than stdout")
+                    .takes_value(true)
+                    .required(false))
+                .arg(Arg::with_name("verbose")
+                    .short("v")
+                    .long("verbose"))
+                .arg(Arg::with_name("quiet")
+                    .short("q")
+                    .long("quiet")))
+        .subcommand(
+            SubCommand::with_name("quant")
+                .about("Run kallisto quant against an index built by the \
+                kallisto subcommand, and sum the abundance of each genome")
+                .arg(Arg::with_name("index-directory")
+                    .short("i")
+                    .long("index-directory")
+                    .help("Output directory of the kallisto subcommand, \
+                    holding genomes.idx and targets_to_genomes.tsv")
+                    .takes_value(true)
+                    .required(true))
+                .arg(Arg::with_name("forward")
+                    .short("1")
+                    .long("forward")
+                    .help("Forward reads of each pair of read files")
+                    .multiple(true)
+                    .takes_value(true)
+                    .requires("reverse")
+                    .required_unless("single"))
+                .arg(Arg::with_name("reverse")
+                    .short("2")
+                    .long("reverse")
+                    .help("Reverse reads, in the same order as --forward")
+                    .multiple(true)
+                    .takes_value(true)
+                    .requires("forward"))
+                .arg(Arg::with_name("single")
+                    .long("single")
+                    .help("Single-end read files, which need --fragment-length \
+                    and --fragment-sd")
+                    .multiple(true)
+                    .takes_value(true)
+                    .conflicts_with_all(&["forward", "reverse"])
+                    .requires_all(&["fragment-length", "fragment-sd"]))
+                .arg(Arg::with_name("fragment-length")
+                    .short("l")
+                    .long("fragment-length")
+                    .help("Mean fragment length, estimated by kallisto from \
+                    paired reads if not given")
+                    .takes_value(true)
+                    .required(false))
+                .arg(Arg::with_name("fragment-sd")
+                    .short("s")
+                    .long("fragment-sd")
+                    .help("Standard deviation of the fragment length")
+                    .takes_value(true)
+                    .required(false))
+                .arg(Arg::with_name("threads")
+                    .short("-t")
+                    .long("threads")
+                    .default_value("1")
+                    .takes_value(true)
+                    .required(false))
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .help("Directory for kallisto's output and genome_abundance.tsv")
+                    .takes_value(true)
+                    .required(true))
+                .arg(Arg::with_name("verbose")
+                    .short("v")
+                    .long("verbose"))
+                .arg(Arg::with_name("quiet")
+                    .short("q")
+                    .long("quiet")))
+        .subcommand(
+            SubCommand::with_name("stats")
+                .about("Calculate the length, contig count, N50, L50, GC \
+                content and N fraction of genome assemblies")
+                .arg(Arg::with_name("fasta-files")
+                    .short("f")
+                    .long("fasta-files")
+                    .conflicts_with("fasta-directory")
+                    .multiple(true)
+                    .takes_value(true)
+                    .required_unless("fasta-directory"))
+                .arg(Arg::with_name("fasta-directory")
+                    .short("d")
+                    .long("fasta-directory")
+                    .help("Manifest of genomes, with a tab-separated accession \
+                    and path column")
+                    .conflicts_with("fasta-files")
+                    .takes_value(true)
+                    .required_unless("fasta-files"))
+                .args(&manifest_args())
+                .arg(Arg::with_name("output")
+                    .short("o")
+                    .long("output")
+                    .help("Write per-genome statistics to this file rather than stdout")
+                    .takes_value(true)
+                    .required(false))
+                .arg(Arg::with_name("contig-output")
+                    .long("contig-output")
+                    .help("Also write per-contig statistics to this file")
+                    .takes_value(true)
+                    .required(false))
+                .arg(Arg::with_name("output-format")
+                    .long("output-format")
+                    .possible_values(&["tsv", "json"])
+                    .default_value("tsv"))
+                .arg(Arg::with_name("verbose")
+                    .short("v")
+                    .long("verbose"))
+                .arg(Arg::with_name("quiet")
+                    .short("q")
+                    .long("quiet")))
+        .subcommand(
+            SubCommand::with_name("validate")
+                .about("Check that every genome file exists, can be \
+                decompressed and starts with a FASTA or FASTQ record")
+                .arg(Arg::with_name("fasta-files")
+                    .short("f")
+                    .long("fasta-files")
+                    .conflicts_with("fasta-directory")
+                    .multiple(true)
+                    .takes_value(true)
+                    .required_unless("fasta-directory"))
+                .arg(Arg::with_name("fasta-directory")
+                    .short("d")
+                    .long("fasta-directory")
+                    .help("Manifest of genomes, with a tab-separated accession \
+                    and path column")
+                    .conflicts_with("fasta-files")
+                    .takes_value(true)
+                    .required_unless("fasta-files"))
+                .args(&manifest_args())
                 .arg(Arg::with_name("verbose")
                     .short("v")
                     .long("verbose"))
@@ -0,0 +1,207 @@
+use std::io;
+use std::path::{Path, PathBuf};
+
+/// Values of the first column which mark the first line of a manifest as a
+/// header rather than a genome.
+pub const HEADER_FIELDS: [&str; 6] = ["accession", "genome", "user_genome", "id", "name", "assembly_accession"];
+
+/// A genome listed in a manifest.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenomeManifestEntry {
+    pub accession: String,
+    /// Path of the genome's FASTA file, after resolution.
+    pub path: PathBuf,
+    /// Taxonomy string from the optional third column.
+    pub taxonomy: Option<String>,
+}
+
+impl GenomeManifestEntry {
+    pub fn path_string(&self) -> String {
+        self.path.to_string_lossy().into_owned()
+    }
+
+    /// The genome's name and path, as genome files are given to the
+    /// readers and writers, so that the genome is named by its accession.
+    pub fn into_genome_file(self) -> (String, String) {
+        let path = self.path_string();
+        (self.accession, path)
+    }
+}
+
+/// How the path column of a manifest is turned into the path of a FASTA
+/// file. Rules are described for a path column of `dir/GCF_1`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PathResolution {
+    /// The first of the other rules which gives an existing file, trying
+    /// `Direct` first. If none exist `GenomicFna` is used, so that the
+    /// missing file is reported as in the GTDB layout.
+    Auto,
+    /// `dir/GCF_1/GCF_1_genomic.fna`, as in GTDB and NCBI downloads.
+    GenomicFna,
+    /// `dir/GCF_1/GCF_1_genomic.fna.gz`
+    GenomicFnaGz,
+    /// `dir/GCF_1.fna`
+    Fna,
+    /// `dir/GCF_1.fna.gz`
+    FnaGz,
+    /// `dir/GCF_1` is the FASTA file itself.
+    Direct,
+}
+
+impl PathResolution {
+    /// Every rule, in the order listed on the command line.
+    pub fn all() -> [PathResolution; 6] {
+        [PathResolution::Auto, PathResolution::GenomicFna, PathResolution::GenomicFnaGz,
+         PathResolution::Fna, PathResolution::FnaGz, PathResolution::Direct]
+    }
+
+    /// The command line representation of a rule.
+    pub fn name(&self) -> &'static str {
+        match *self {
+            PathResolution::Auto => "auto",
+            PathResolution::GenomicFna => "genomic-fna",
+            PathResolution::GenomicFnaGz => "genomic-fna-gz",
+            PathResolution::Fna => "fna",
+            PathResolution::FnaGz => "fna-gz",
+            PathResolution::Direct => "direct",
+        }
+    }
+
+    /// Parse the command line representation of a rule.
+    pub fn from_name(name: &str) -> Option<PathResolution> {
+        PathResolution::all().iter().cloned().find(|rule| rule.name() == name)
+    }
+
+    /// Return the FASTA file path for a path column.
+    pub fn resolve(&self, path: &str) -> PathBuf {
+        let trimmed = path.trim_end_matches('/');
+        let basename = trimmed.rsplit('/').next().unwrap_or(trimmed);
+        match *self {
+            PathResolution::Auto => {
+                let candidates = [PathResolution::Direct, PathResolution::GenomicFna,
+                                  PathResolution::GenomicFnaGz, PathResolution::Fna,
+                                  PathResolution::FnaGz];
+                candidates.iter()
+                    .map(|rule| rule.resolve(path))
+                    .find(|candidate| candidate.is_file())
+                    .unwrap_or_else(|| PathResolution::GenomicFna.resolve(path))
+            },
+            PathResolution::GenomicFna => Path::new(trimmed).join(format!("{}_genomic.fna", basename)),
+            PathResolution::GenomicFnaGz => Path::new(trimmed).join(format!("{}_genomic.fna.gz", basename)),
+            PathResolution::Fna => PathBuf::from(format!("{}.fna", trimmed)),
+            PathResolution::FnaGz => PathBuf::from(format!("{}.fna.gz", trimmed)),
+            PathResolution::Direct => PathBuf::from(path),
+        }
+    }
+}
+
+fn is_header(fields: &[&str]) -> bool {
+    let first = fields[0].to_lowercase();
+    HEADER_FIELDS.iter().any(|h| *h == first)
+}
+
+/// Parse a tab-separated manifest with an accession column, a path column
+/// and an optional taxonomy column. A header line is skipped if its first
+/// column is one of `HEADER_FIELDS`, as are blank lines and lines starting
+/// with `#`. A row with the wrong number of columns or an empty accession
+/// or path is an error naming its line.
+pub fn parse_manifest<R: io::BufRead>(reader: R, resolution: PathResolution)
+    -> io::Result<Vec<GenomeManifestEntry>> {
+    let mut entries = vec!();
+    let mut seen_row = false;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() || line.starts_with('#') {
+            continue
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if !seen_row {
+            seen_row = true;
+            if is_header(&fields) {
+                continue
+            }
+        }
+        if fields.len() < 2 || fields.len() > 3 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Expected accession, path and optional taxonomy columns separated \
+                         by tabs on line {} of manifest, found {} column(s)",
+                        i+1, fields.len())))
+        }
+        if fields[0].trim().is_empty() || fields[1].trim().is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Empty accession or path on line {} of manifest", i+1)))
+        }
+        entries.push(GenomeManifestEntry {
+            accession: fields[0].trim().to_string(),
+            path: resolution.resolve(fields[1].trim()),
+            taxonomy: fields.get(2)
+                .map(|t| t.trim())
+                .filter(|t| !t.is_empty())
+                .map(|t| t.to_string()),
+        });
+    }
+    Ok(entries)
+}
+
+/// Read a manifest file, as `parse_manifest` does.
+pub fn read_manifest<P: AsRef<Path>>(path: P, resolution: PathResolution)
+    -> io::Result<Vec<GenomeManifestEntry>> {
+    let file = ::open_with_context(path, "manifest")?;
+    parse_manifest(io::BufReader::new(file), resolution)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_resolve(){
+        assert_eq!(PathBuf::from("gtdb/GCF_1/GCF_1_genomic.fna"),
+                   PathResolution::GenomicFna.resolve("gtdb/GCF_1/"));
+        assert_eq!(PathBuf::from("gtdb/GCF_1/GCF_1_genomic.fna.gz"),
+                   PathResolution::GenomicFnaGz.resolve("gtdb/GCF_1"));
+        assert_eq!(PathBuf::from("gtdb/GCF_1.fna.gz"), PathResolution::FnaGz.resolve("gtdb/GCF_1"));
+        assert_eq!(PathBuf::from("tests/data/genome1.fna"),
+                   PathResolution::Auto.resolve("tests/data/genome1.fna"));
+        assert_eq!(PathBuf::from("tests/data/genome1.fna"),
+                   PathResolution::Auto.resolve("tests/data/genome1"));
+        assert_eq!(PathBuf::from("missing/missing_genomic.fna"),
+                   PathResolution::Auto.resolve("missing"));
+        for rule in &PathResolution::all() {
+            assert_eq!(Some(*rule), PathResolution::from_name(rule.name()));
+        }
+        assert_eq!(None, PathResolution::from_name("other"));
+    }
+
+    #[test]
+    fn test_parse_manifest(){
+        let manifest = "accession\tpath\ttaxonomy\n\
+                        GCF_1\tgtdb/GCF_1\td__Bacteria;p__Proteobacteria\n\
+                        \n\
+                        # comment\n\
+                        GCA_2\tgtdb/GCA_2\n";
+        let entries = parse_manifest(manifest.as_bytes(), PathResolution::GenomicFna).unwrap();
+        assert_eq!(vec![
+            GenomeManifestEntry {
+                accession: "GCF_1".to_string(),
+                path: PathBuf::from("gtdb/GCF_1/GCF_1_genomic.fna"),
+                taxonomy: Some("d__Bacteria;p__Proteobacteria".to_string()),
+            },
+            GenomeManifestEntry {
+                accession: "GCA_2".to_string(),
+                path: PathBuf::from("gtdb/GCA_2/GCA_2_genomic.fna"),
+                taxonomy: None,
+            }], entries);
+
+        let entries = parse_manifest("GCF_1\tg.fna\n".as_bytes(), PathResolution::Direct).unwrap();
+        assert_eq!("g.fna", entries[0].path_string());
+
+        let error = parse_manifest("GCF_1\tg.fna\nGCF_2\n".as_bytes(), PathResolution::Direct)
+            .unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+        assert!(parse_manifest("GCF_1\t\n".as_bytes(), PathResolution::Direct).is_err());
+    }
+}
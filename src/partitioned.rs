@@ -0,0 +1,257 @@
+use std::collections::{HashMap, VecDeque};
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::mem;
+use std::path::{Path, PathBuf};
+
+use kmer::{Kmer, WindowCounts};
+use kmer_table::{binary_kmer_bytes, KmerTableWriter};
+
+/// Number of leading bases used to choose the partition of a k-mer, giving
+/// up to 4^4 = 256 partition files. A partition too large for the memory
+/// budget is split by this many following bases in turn.
+pub const PARTITION_PREFIX_BASES: usize = 4;
+
+/// Parse a memory size given as a number of bytes, optionally followed by
+/// K, M, G or T for powers of 1024.
+pub fn parse_memory_size(size: &str) -> Option<usize> {
+    let size = size.trim();
+    let (number, multiplier) = match size.chars().last() {
+        Some('K') | Some('k') => (&size[..size.len()-1], 1 << 10),
+        Some('M') | Some('m') => (&size[..size.len()-1], 1 << 20),
+        Some('G') | Some('g') => (&size[..size.len()-1], 1 << 30),
+        Some('T') | Some('t') => (&size[..size.len()-1], 1usize << 40),
+        _ => (size, 1),
+    };
+    number.parse::<usize>().ok().and_then(|n| n.checked_mul(multiplier))
+}
+
+/// Counts k-mers of many genomes within a memory budget. Each genome is read
+/// and counted on its own, and its counts are spilled to one of a set of
+/// partition files chosen by the leading bases of each k-mer. Partitions
+/// too large for the budget are split by the bases which follow, until
+/// they fit or hold a single k-mer. Runs of consecutive partitions which
+/// fit in the budget are then loaded and merged into rows, which come out
+/// in the same sorted order as from an in-memory `KmerMap`.
+pub struct PartitionedCounter {
+    kmer_size: usize,
+    canonical: bool,
+    max_memory: usize,
+    directory: PathBuf,
+}
+
+/// A partition file holding the records of k-mers which start with the
+/// same `prefix_bases` bases.
+struct Partition {
+    path: PathBuf,
+    num_records: usize,
+    prefix_bases: usize,
+}
+
+impl PartitionedCounter {
+    /// Create a counter writing its partition files into the given
+    /// directory, which should be empty and is left for the caller to
+    /// remove.
+    pub fn new<P: AsRef<Path>>(kmer_size: usize, canonical: bool, max_memory: usize,
+                               directory: P) -> PartitionedCounter {
+        PartitionedCounter {
+            kmer_size,
+            canonical,
+            max_memory,
+            directory: directory.as_ref().to_path_buf(),
+        }
+    }
+
+    fn prefix_bases(&self) -> usize {
+        ::std::cmp::min(self.kmer_size, PARTITION_PREFIX_BASES)
+    }
+
+    fn num_partitions(&self) -> usize {
+        1 << (2 * self.prefix_bases())
+    }
+
+    fn partition_path(&self, partition: usize) -> PathBuf {
+        self.directory.join(format!("partition_{}.bin", partition))
+    }
+
+    /// Split a partition by up to `PARTITION_PREFIX_BASES` further bases,
+    /// returning the new partitions in sorted order. Their files are
+    /// numbered from `next_partition` on.
+    fn split(&self, partition: Partition, record_bytes: usize, next_partition: &mut usize)
+        -> io::Result<Vec<Partition>> {
+        let kmer_bytes = record_bytes - 8;
+        let bases = ::std::cmp::min(PARTITION_PREFIX_BASES, self.kmer_size - partition.prefix_bases);
+        let prefix_bases = partition.prefix_bases + bases;
+        let shift = 2 * (self.kmer_size - prefix_bases);
+        let mask = (1u128 << (2 * bases)) - 1;
+
+        let mut parts = vec!();
+        let mut writers = vec!();
+        for _ in 0..(1 << (2 * bases)) {
+            let path = self.partition_path(*next_partition);
+            *next_partition += 1;
+            writers.push(io::BufWriter::new(fs::File::create(&path)?));
+            parts.push(Partition { path, num_records: 0, prefix_bases });
+        }
+        let mut reader = io::BufReader::new(fs::File::open(&partition.path)?);
+        let mut record = vec![0u8; record_bytes];
+        for _ in 0..partition.num_records {
+            reader.read_exact(&mut record)?;
+            let mut kmer = [0u8; 16];
+            kmer[..kmer_bytes].copy_from_slice(&record[..kmer_bytes]);
+            let part = ((u128::from_le_bytes(kmer) >> shift) & mask) as usize;
+            writers[part].write_all(&record)?;
+            parts[part].num_records += 1;
+        }
+        for mut writer in writers {
+            writer.flush()?;
+        }
+        fs::remove_file(&partition.path)?;
+        Ok(parts)
+    }
+
+    /// Estimated bytes of memory used by each distinct k-mer while merging.
+    /// A budget below this for a single k-mer cannot be kept to.
+    pub fn bytes_per_kmer<T: Kmer>(&self, num_genomes: usize) -> usize {
+        // Key, Vec header, counts and HashMap overhead.
+        mem::size_of::<T>() + mem::size_of::<Vec<u32>>() + 4 * num_genomes + 16
+    }
+
+    /// Count the k-mers of each file, read as one genome with the name it is
+    /// paired with, given as `(name, path)`, and write the table. Returns
+    /// the number of windows counted and skipped in each genome.
+    pub fn count<T: Kmer>(&self, genome_files: &[(&str, &str)], writer: &mut dyn KmerTableWriter)
+        -> io::Result<Vec<WindowCounts>> {
+        let kmer_bytes = binary_kmer_bytes(self.kmer_size);
+        let record_bytes = kmer_bytes + 8;
+        let shift = 2 * (self.kmer_size - self.prefix_bases());
+
+        fs::create_dir_all(&self.directory)?;
+        let mut partitions = Vec::with_capacity(self.num_partitions());
+        for partition in 0..self.num_partitions() {
+            partitions.push(io::BufWriter::new(fs::File::create(self.partition_path(partition))?));
+        }
+        let mut next_partition = self.num_partitions();
+        let mut partition_records = vec![0usize; self.num_partitions()];
+        let mut genomes = vec!();
+        let mut window_counts = vec!();
+        for (genome_index, genome_file) in genome_files.iter().enumerate() {
+            let genome = ::read_named_genome_fasta_files(&[*genome_file])?;
+            let (counts, genome_window_counts) = genome.count_genome_kmers::<T>(
+                0, self.kmer_size, self.canonical);
+            for (kmer, count) in counts {
+                let kmer = kmer.to_u128();
+                let partition = (kmer >> shift) as usize;
+                let writer = &mut partitions[partition];
+                writer.write_all(&kmer.to_le_bytes()[..kmer_bytes])?;
+                writer.write_all(&(genome_index as u32).to_le_bytes())?;
+                writer.write_all(&count.to_le_bytes())?;
+                partition_records[partition] += 1;
+            }
+            genomes.push(genome_file.0.to_string());
+            window_counts.push(genome_window_counts);
+        }
+        for mut partition in partitions {
+            partition.flush()?;
+        }
+
+        writer.write_header(&genomes, self.kmer_size, self.canonical)?;
+        // Every record may be a distinct k-mer, so batch partitions by their
+        // number of records. A partition of a single k-mer is loaded alone
+        // however many records it has, as it makes only one row.
+        let max_kmers = ::std::cmp::max(1, self.max_memory / self.bytes_per_kmer::<T>(genomes.len()));
+        let mut pending: VecDeque<Partition> = partition_records.into_iter().enumerate()
+            .map(|(partition, num_records)| Partition {
+                path: self.partition_path(partition),
+                num_records,
+                prefix_bases: self.prefix_bases(),
+            }).collect();
+        loop {
+            let mut batch = vec!();
+            let mut batch_records = 0;
+            while let Some(partition) = pending.pop_front() {
+                if partition.num_records > max_kmers && partition.prefix_bases < self.kmer_size {
+                    for part in self.split(partition, record_bytes, &mut next_partition)?.into_iter().rev() {
+                        pending.push_front(part);
+                    }
+                } else if batch.is_empty() || batch_records + partition.num_records <= max_kmers {
+                    batch_records += partition.num_records;
+                    batch.push(partition);
+                } else {
+                    pending.push_front(partition);
+                    break
+                }
+            }
+            if batch.is_empty() {
+                break
+            }
+            let mut kmers: HashMap<T, Vec<u32>> = HashMap::with_capacity(batch_records);
+            let mut record = vec![0u8; record_bytes];
+            for partition in batch {
+                let mut reader = io::BufReader::new(fs::File::open(&partition.path)?);
+                for _ in 0..partition.num_records {
+                    reader.read_exact(&mut record)?;
+                    let mut kmer = [0u8; 16];
+                    kmer[..kmer_bytes].copy_from_slice(&record[..kmer_bytes]);
+                    let mut genome_index = [0u8; 4];
+                    genome_index.copy_from_slice(&record[kmer_bytes..kmer_bytes+4]);
+                    let mut count = [0u8; 4];
+                    count.copy_from_slice(&record[kmer_bytes+4..]);
+                    kmers.entry(T::from_u128(u128::from_le_bytes(kmer)))
+                        .or_insert(vec![0; genomes.len()])
+                        [u32::from_le_bytes(genome_index) as usize] = u32::from_le_bytes(count);
+                }
+                fs::remove_file(&partition.path)?;
+            }
+            let mut sorted: Vec<T> = kmers.keys().cloned().collect();
+            sorted.sort();
+            for kmer in sorted {
+                writer.write_row(kmer.to_u128(), &kmers[&kmer])?;
+            }
+        }
+        writer.finish()?;
+        Ok(window_counts)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use kmer_table::TsvWriter;
+
+    #[test]
+    fn test_parse_memory_size(){
+        assert_eq!(Some(100), parse_memory_size("100"));
+        assert_eq!(Some(2048), parse_memory_size("2K"));
+        assert_eq!(Some(3 << 30), parse_memory_size("3G"));
+        assert_eq!(None, parse_memory_size("lots"));
+    }
+
+    #[test]
+    fn test_partitioned_matches_in_memory(){
+        let paths = vec!["tests/data/7seqs.fna", "tests/data/genome1.fna", "tests/data/genome1.fna.gz"];
+        let kmer_map = ::read_genome_fasta_files_as_one_genome(&paths).unwrap()
+            .establish_kmers::<u64>(7, true);
+        let mut expected = TsvWriter::new(Vec::new());
+        kmer_map.write_table(&mut expected).unwrap();
+        let expected = expected.into_inner().unwrap();
+
+        let dir = ::tempfile::tempdir().unwrap();
+        // A small budget forces partitions to be merged in many batches.
+        let counter = PartitionedCounter::new(7, true, 100_000, dir.path());
+        let mut written = TsvWriter::new(Vec::new());
+        let named: Vec<(&str, &str)> = paths.iter().map(|p| (*p, *p)).collect();
+        let window_counts = counter.count::<u64>(&named, &mut written).unwrap();
+        assert_eq!(kmer_map.window_counts, window_counts);
+        assert_eq!(expected, written.into_inner().unwrap());
+        assert_eq!(0, fs::read_dir(dir.path()).unwrap().count());
+
+        // A budget below one row splits partitions down to single k-mers.
+        let counter = PartitionedCounter::new(7, true, 1, dir.path());
+        let mut written = TsvWriter::new(Vec::new());
+        counter.count::<u64>(&named, &mut written).unwrap();
+        assert_eq!(expected, written.into_inner().unwrap());
+        assert_eq!(0, fs::read_dir(dir.path()).unwrap().count());
+    }
+}
@@ -0,0 +1,258 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io;
+use std::path::Path;
+
+use manifest::GenomeManifestEntry;
+use taxonomy::normalise_accession;
+
+/// Header names of the accession column in GTDB metadata, CheckM and
+/// CheckM2 output. The first column is used if none are present.
+pub const ACCESSION_COLUMNS: [&str; 4] = ["accession", "Bin Id", "Name", "user_genome"];
+pub const COMPLETENESS_COLUMNS: [&str; 3] = ["checkm_completeness", "checkm2_completeness", "Completeness"];
+pub const CONTAMINATION_COLUMNS: [&str; 3] = ["checkm_contamination", "checkm2_contamination", "Contamination"];
+pub const CONTIG_COUNT_COLUMNS: [&str; 3] = ["contig_count", "# contigs", "Total_Contigs"];
+pub const N50_COLUMNS: [&str; 3] = ["n50_contigs", "N50 (contigs)", "Contig_N50"];
+
+/// Quality estimates of one genome. Each is None if the file has no such
+/// column or gives no value.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct GenomeQuality {
+    /// Percentage completeness.
+    pub completeness: Option<f64>,
+    /// Percentage contamination.
+    pub contamination: Option<f64>,
+    pub contig_count: Option<u64>,
+    pub n50: Option<u64>,
+}
+
+/// Limits a genome must be within to be used. Unset limits are not checked.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct QualityThresholds {
+    pub min_completeness: Option<f64>,
+    pub max_contamination: Option<f64>,
+    pub max_contigs: Option<u64>,
+    pub min_n50: Option<u64>,
+}
+
+impl QualityThresholds {
+    pub fn is_empty(&self) -> bool {
+        *self == QualityThresholds::default()
+    }
+
+    /// Reasons the genome fails the thresholds, empty if it passes. A value
+    /// which is needed but unknown fails.
+    pub fn failures(&self, quality: &GenomeQuality) -> Vec<String> {
+        fn check<T: PartialOrd + fmt::Display>(failures: &mut Vec<String>, name: &str,
+                                               value: Option<T>, limit: Option<T>, minimum: bool) {
+            if let Some(limit) = limit {
+                match value {
+                    None => failures.push(format!("{} unknown", name)),
+                    Some(value) => if minimum && value < limit {
+                        failures.push(format!("{} {} < {}", name, value, limit))
+                    } else if !minimum && value > limit {
+                        failures.push(format!("{} {} > {}", name, value, limit))
+                    }
+                }
+            }
+        }
+        let mut failures = vec!();
+        check(&mut failures, "completeness", quality.completeness, self.min_completeness, true);
+        check(&mut failures, "contamination", quality.contamination, self.max_contamination, false);
+        check(&mut failures, "contig count", quality.contig_count, self.max_contigs, false);
+        check(&mut failures, "N50", quality.n50, self.min_n50, true);
+        failures
+    }
+}
+
+/// A genome left out for failing the quality thresholds.
+#[derive(Debug, Clone, PartialEq)]
+pub struct DroppedGenome {
+    pub entry: GenomeManifestEntry,
+    pub reasons: Vec<String>,
+}
+
+/// Quality estimates by normalised accession.
+#[derive(Debug, Default, PartialEq)]
+pub struct QualityTable {
+    qualities: HashMap<String, GenomeQuality>,
+}
+
+impl QualityTable {
+    pub fn new() -> QualityTable {
+        QualityTable::default()
+    }
+
+    pub fn insert(&mut self, accession: &str, quality: GenomeQuality) {
+        self.qualities.insert(normalise_accession(accession).to_string(), quality);
+    }
+
+    /// The quality of a genome. The accession may have an `RS_` or `GB_`
+    /// prefix or not.
+    pub fn get(&self, accession: &str) -> Option<&GenomeQuality> {
+        self.qualities.get(normalise_accession(accession))
+    }
+
+    pub fn len(&self) -> usize {
+        self.qualities.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.qualities.is_empty()
+    }
+
+    /// Split manifest entries into those which pass the thresholds and
+    /// those which do not, keeping their order. Genomes missing from the
+    /// table fail any threshold.
+    pub fn filter(&self, entries: Vec<GenomeManifestEntry>, thresholds: &QualityThresholds)
+        -> (Vec<GenomeManifestEntry>, Vec<DroppedGenome>) {
+        let mut kept = vec!();
+        let mut dropped = vec!();
+        for entry in entries {
+            let reasons = match self.get(&entry.accession) {
+                Some(quality) => thresholds.failures(quality),
+                None if thresholds.is_empty() => vec!(),
+                None => vec!("not in quality file".to_string()),
+            };
+            if reasons.is_empty() {
+                kept.push(entry);
+            } else {
+                dropped.push(DroppedGenome { entry, reasons });
+            }
+        }
+        (kept, dropped)
+    }
+}
+
+fn find_column(header: &[&str], names: &[&str]) -> Option<usize> {
+    names.iter().filter_map(|name| header.iter().position(|h| h == name)).next()
+}
+
+/// Parse a tab-separated table of genome quality with a header line, as
+/// GTDB metadata files such as `bac120_metadata.tsv`, `checkm qa
+/// --tab_table` and CheckM2's `quality_report.tsv` are. Columns are found
+/// by their names in the header. Empty values and `none` are unknown, while
+/// other values which are not numbers, or a missing accession, fail with
+/// the line they are on.
+pub fn parse_quality<R: io::BufRead>(reader: R) -> io::Result<QualityTable> {
+    let mut table = QualityTable::new();
+    let mut columns = None;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue
+        }
+        let fields: Vec<&str> = line.split('\t').map(|f| f.trim()).collect();
+        let (accession, completeness, contamination, contig_count, n50) = match columns {
+            Some(columns) => columns,
+            None => {
+                columns = Some((
+                    find_column(&fields, &ACCESSION_COLUMNS).unwrap_or(0),
+                    find_column(&fields, &COMPLETENESS_COLUMNS),
+                    find_column(&fields, &CONTAMINATION_COLUMNS),
+                    find_column(&fields, &CONTIG_COUNT_COLUMNS),
+                    find_column(&fields, &N50_COLUMNS)));
+                continue
+            }
+        };
+        let value = |column: Option<usize>, name: &str| -> io::Result<Option<f64>> {
+            match column.map(|c| fields.get(c).cloned().unwrap_or("")) {
+                None | Some("") | Some("none") => Ok(None),
+                Some(value) => value.parse::<f64>().map(Some).map_err(|_| io::Error::new(
+                    io::ErrorKind::InvalidData,
+                    format!("Invalid {} '{}' on line {} of quality file", name, value, i+1))),
+            }
+        };
+        let genome = fields.get(accession).cloned().unwrap_or("");
+        if genome.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Missing accession on line {} of quality file", i+1)))
+        }
+        table.insert(genome, GenomeQuality {
+            completeness: value(completeness, "completeness")?,
+            contamination: value(contamination, "contamination")?,
+            contig_count: value(contig_count, "contig count")?.map(|v| v as u64),
+            n50: value(n50, "N50")?.map(|v| v as u64),
+        });
+    }
+    Ok(table)
+}
+
+/// Read a quality file, as `parse_quality` does.
+pub fn read_quality<P: AsRef<Path>>(path: P) -> io::Result<QualityTable> {
+    let file = ::open_with_context(path, "quality file")?;
+    parse_quality(io::BufReader::new(file))
+}
+
+/// Write the dropped genomes as TSV with their accession, path and the
+/// reasons they were dropped, separated by `; `.
+pub fn write_dropped_report<W: io::Write>(dropped: &[DroppedGenome], mut writer: W) -> io::Result<()> {
+    writeln!(writer, "accession\tpath\treasons")?;
+    for genome in dropped {
+        writeln!(writer, "{}\t{}\t{}", genome.entry.accession, genome.entry.path_string(),
+                 genome.reasons.join("; "))?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    fn entry(accession: &str) -> GenomeManifestEntry {
+        GenomeManifestEntry {
+            accession: accession.to_string(),
+            path: PathBuf::from(format!("{}.fna", accession)),
+            taxonomy: None,
+        }
+    }
+
+    #[test]
+    fn test_parse_quality(){
+        let metadata = "accession\tcheckm_completeness\tcheckm_contamination\tcontig_count\tn50_contigs\n\
+                        RS_GCF_1\t99.5\t0.2\t12\t500000\n\
+                        GB_GCA_2\t85.1\t6.3\t310\t\n";
+        let table = parse_quality(metadata.as_bytes()).unwrap();
+        assert_eq!(2, table.len());
+        assert_eq!(Some(&GenomeQuality {
+            completeness: Some(85.1), contamination: Some(6.3), contig_count: Some(310), n50: None,
+        }), table.get("GCA_2"));
+
+        let checkm = "Bin Id\tMarker lineage\tCompleteness\tContamination\t# contigs\tN50 (contigs)\n\
+                      bin.1\tk__Bacteria\t92.0\t1.5\t80\t25000\n";
+        let table = parse_quality(checkm.as_bytes()).unwrap();
+        assert_eq!(Some(92.0), table.get("bin.1").unwrap().completeness);
+        assert_eq!(Some(25000), table.get("bin.1").unwrap().n50);
+
+        let error = parse_quality("Name\tCompleteness\nbin.1\thigh\n".as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+    }
+
+    #[test]
+    fn test_filter(){
+        let mut table = QualityTable::new();
+        table.insert("GCF_1", GenomeQuality { completeness: Some(99.5), contamination: Some(0.2),
+                                              contig_count: Some(12), n50: None });
+        table.insert("GCA_2", GenomeQuality { completeness: Some(85.1), contamination: Some(6.3),
+                                              contig_count: Some(310), n50: None });
+        let thresholds = QualityThresholds {
+            min_completeness: Some(90.0), max_contamination: Some(5.0), ..Default::default()
+        };
+        let (kept, dropped) = table.filter(vec![entry("GCF_1"), entry("GCA_2"), entry("GCF_3")], &thresholds);
+        assert_eq!(vec![entry("GCF_1")], kept);
+        assert_eq!(vec!["completeness 85.1 < 90".to_string(), "contamination 6.3 > 5".to_string()],
+                   dropped[0].reasons);
+        assert_eq!(vec!["not in quality file".to_string()], dropped[1].reasons);
+
+        let thresholds = QualityThresholds { min_n50: Some(1000), ..Default::default() };
+        assert_eq!(vec!["N50 unknown".to_string()], thresholds.failures(table.get("GCF_1").unwrap()));
+
+        let mut report = Vec::new();
+        write_dropped_report(&dropped[1..], &mut report).unwrap();
+        assert_eq!("accession\tpath\treasons\nGCF_3\tGCF_3.fna\tnot in quality file\n",
+                   String::from_utf8(report).unwrap());
+    }
+}
@@ -0,0 +1,311 @@
+use std::collections::HashMap;
+use std::ffi::OsString;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+use external_command::{self, ExternalCommandError};
+
+/// Name of the kallisto index written into the output directory of the
+/// `kallisto` subcommand.
+pub const INDEX_FILE_NAME: &str = "genomes.idx";
+/// Name of the per-target abundances written by `kallisto quant`.
+pub const ABUNDANCE_FILE_NAME: &str = "abundance.tsv";
+/// Name of the per-genome abundances written next to `abundance.tsv`.
+pub const GENOME_ABUNDANCE_FILE_NAME: &str = "genome_abundance.tsv";
+
+/// Reads given to `kallisto quant`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum QuantReads {
+    /// Single-end reads, with the mean and standard deviation of the
+    /// fragment length, which kallisto cannot estimate from them.
+    Single {
+        files: Vec<PathBuf>,
+        fragment_length: f64,
+        fragment_sd: f64,
+    },
+    /// Pairs of forward and reverse read files.
+    Paired(Vec<(PathBuf, PathBuf)>),
+}
+
+/// Assembles the arguments of a `kallisto quant` run against an index
+/// built by the `kallisto` subcommand.
+#[derive(Debug, Clone)]
+pub struct KallistoQuantBuilder {
+    index: PathBuf,
+    output_directory: PathBuf,
+    reads: QuantReads,
+    fragment_length: Option<f64>,
+    fragment_sd: Option<f64>,
+    threads: usize,
+}
+
+impl KallistoQuantBuilder {
+    pub fn new<I: AsRef<Path>, P: AsRef<Path>>(index: I, output_directory: P, reads: QuantReads)
+        -> KallistoQuantBuilder {
+        KallistoQuantBuilder {
+            index: index.as_ref().to_path_buf(),
+            output_directory: output_directory.as_ref().to_path_buf(),
+            reads,
+            fragment_length: None,
+            fragment_sd: None,
+            threads: 1,
+        }
+    }
+
+    /// Mean fragment length of paired reads, rather than kallisto's
+    /// estimate. Single-end reads give their own.
+    pub fn fragment_length(mut self, fragment_length: f64) -> KallistoQuantBuilder {
+        self.fragment_length = Some(fragment_length);
+        self
+    }
+
+    /// Standard deviation of the fragment length of paired reads.
+    pub fn fragment_sd(mut self, fragment_sd: f64) -> KallistoQuantBuilder {
+        self.fragment_sd = Some(fragment_sd);
+        self
+    }
+
+    pub fn threads(mut self, threads: usize) -> KallistoQuantBuilder {
+        self.threads = threads;
+        self
+    }
+
+    pub fn abundance_path(&self) -> PathBuf {
+        self.output_directory.join(ABUNDANCE_FILE_NAME)
+    }
+
+    /// The arguments passed to kallisto, each as a separate entry.
+    pub fn args(&self) -> Vec<OsString> {
+        let mut args: Vec<OsString> = vec![
+            "quant".into(),
+            "-i".into(),
+            self.index.clone().into(),
+            "-o".into(),
+            self.output_directory.clone().into(),
+        ];
+        let (fragment_length, fragment_sd) = match self.reads {
+            QuantReads::Single { fragment_length, fragment_sd, .. } => {
+                args.push("--single".into());
+                (Some(fragment_length), Some(fragment_sd))
+            },
+            QuantReads::Paired(_) => (self.fragment_length, self.fragment_sd),
+        };
+        if let Some(fragment_length) = fragment_length {
+            args.push("-l".into());
+            args.push(fragment_length.to_string().into());
+        }
+        if let Some(fragment_sd) = fragment_sd {
+            args.push("-s".into());
+            args.push(fragment_sd.to_string().into());
+        }
+        args.push("-t".into());
+        args.push(self.threads.to_string().into());
+        match self.reads {
+            QuantReads::Single { ref files, .. } => {
+                for file in files {
+                    args.push(file.into());
+                }
+            },
+            QuantReads::Paired(ref pairs) => {
+                for (forward, reverse) in pairs {
+                    args.push(forward.into());
+                    args.push(reverse.into());
+                }
+            }
+        }
+        args
+    }
+
+    pub fn command(&self) -> Command {
+        let mut command = Command::new("kallisto");
+        command.args(self.args());
+        command
+    }
+
+    /// Run kallisto, returning an error if it fails.
+    pub fn run(&self) -> Result<Output, ExternalCommandError> {
+        fs::create_dir_all(&self.output_directory)?;
+        external_command::run_command(&mut self.command(), "kallisto")
+    }
+}
+
+/// One row of a kallisto `abundance.tsv`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TargetAbundance {
+    pub target: String,
+    pub length: u64,
+    pub eff_length: f64,
+    pub est_counts: f64,
+    pub tpm: f64,
+}
+
+/// Abundance of a genome, summed over its targets.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenomeAbundance {
+    pub genome: String,
+    pub num_targets: usize,
+    pub est_counts: f64,
+    pub tpm: f64,
+}
+
+/// Parse a kallisto `abundance.tsv`, which has a header line and the
+/// columns target_id, length, eff_length, est_counts and tpm.
+pub fn parse_abundance<R: io::BufRead>(reader: R) -> io::Result<Vec<TargetAbundance>> {
+    let mut abundances = vec!();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if i == 0 || line.is_empty() {
+            continue
+        }
+        let invalid = || io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Expected target_id, length, eff_length, est_counts and tpm columns \
+                     on line {} of abundances", i+1));
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 5 {
+            return Err(invalid())
+        }
+        abundances.push(TargetAbundance {
+            target: fields[0].to_string(),
+            length: fields[1].parse().map_err(|_| invalid())?,
+            eff_length: fields[2].parse().map_err(|_| invalid())?,
+            est_counts: fields[3].parse().map_err(|_| invalid())?,
+            tpm: fields[4].parse().map_err(|_| invalid())?,
+        });
+    }
+    Ok(abundances)
+}
+
+/// Read a kallisto `abundance.tsv`, as `parse_abundance` does.
+pub fn read_abundance<P: AsRef<Path>>(path: P) -> io::Result<Vec<TargetAbundance>> {
+    let file = ::open_with_context(path, "abundances")?;
+    parse_abundance(io::BufReader::new(file))
+}
+
+/// Sum the estimated counts and TPM of the targets of each genome, using a
+/// mapping as read by `targets::read_targets_to_genomes`. Genomes are in
+/// the order their first target appears. Returns an error if a target is
+/// missing from the mapping, as happens if the index was built separately.
+pub fn abundance_by_genome(abundances: &[TargetAbundance], targets_to_genomes: &HashMap<String, String>)
+    -> io::Result<Vec<GenomeAbundance>> {
+    let mut genomes: Vec<GenomeAbundance> = vec!();
+    let mut genome_indices: HashMap<&str, usize> = HashMap::new();
+    for abundance in abundances {
+        let genome = targets_to_genomes.get(&abundance.target).ok_or_else(|| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Target {} is not in the target to genome mapping", abundance.target)))?;
+        let index = *genome_indices.entry(genome.as_str()).or_insert_with(|| {
+            genomes.push(GenomeAbundance {
+                genome: genome.clone(), num_targets: 0, est_counts: 0.0, tpm: 0.0,
+            });
+            genomes.len() - 1
+        });
+        let genome_abundance = &mut genomes[index];
+        genome_abundance.num_targets += 1;
+        genome_abundance.est_counts += abundance.est_counts;
+        genome_abundance.tpm += abundance.tpm;
+    }
+    Ok(genomes)
+}
+
+/// Write genome abundances as TSV with a header line.
+pub fn write_genome_abundances<W: io::Write>(abundances: &[GenomeAbundance], writer: W) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(writer);
+    writeln!(writer, "genome\tnum_targets\test_counts\ttpm")?;
+    for abundance in abundances {
+        writeln!(writer, "{}\t{}\t{}\t{}", abundance.genome, abundance.num_targets,
+                 abundance.est_counts, abundance.tpm)?;
+    }
+    writer.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use manifest::{self, GenomeManifestEntry, PathResolution};
+    use targets::{TargetNamer, TargetNaming};
+
+    #[test]
+    fn test_quant_args(){
+        let builder = KallistoQuantBuilder::new("idx/genomes.idx", "out", QuantReads::Single {
+            files: vec![PathBuf::from("a.fq"), PathBuf::from("b.fq")],
+            fragment_length: 200.0,
+            fragment_sd: 20.5,
+        }).threads(4);
+        assert_eq!(
+            vec!["quant", "-i", "idx/genomes.idx", "-o", "out", "--single", "-l", "200",
+                 "-s", "20.5", "-t", "4", "a.fq", "b.fq"],
+            builder.args());
+
+        let builder = KallistoQuantBuilder::new("genomes.idx", "out", QuantReads::Paired(vec![
+            (PathBuf::from("a_1.fq"), PathBuf::from("a_2.fq")),
+            (PathBuf::from("b_1.fq"), PathBuf::from("b_2.fq"))]));
+        assert_eq!(
+            vec!["quant", "-i", "genomes.idx", "-o", "out", "-t", "1",
+                 "a_1.fq", "a_2.fq", "b_1.fq", "b_2.fq"],
+            builder.args());
+        assert_eq!(PathBuf::from("out/abundance.tsv"), builder.abundance_path());
+        assert_eq!(
+            vec!["quant", "-i", "genomes.idx", "-o", "out", "-l", "300", "-t", "1",
+                 "a_1.fq", "a_2.fq", "b_1.fq", "b_2.fq"],
+            builder.fragment_length(300.0).args());
+    }
+
+    #[test]
+    fn test_abundance_by_genome(){
+        let abundance = "target_id\tlength\teff_length\test_counts\ttpm\n\
+                         g1|c1\t1000\t801\t10\t250000\n\
+                         g2|c1\t500\t301\t4.5\t300000\n\
+                         g1|c2\t2000\t1801\t30\t450000\n";
+        let abundances = parse_abundance(abundance.as_bytes()).unwrap();
+        assert_eq!(3, abundances.len());
+        assert_eq!(4.5, abundances[1].est_counts);
+
+        let mut targets_to_genomes = HashMap::new();
+        for &(target, genome) in &[("g1|c1", "g1"), ("g1|c2", "g1"), ("g2|c1", "g2")] {
+            targets_to_genomes.insert(target.to_string(), genome.to_string());
+        }
+        let genomes = abundance_by_genome(&abundances, &targets_to_genomes).unwrap();
+        let mut written = Vec::new();
+        write_genome_abundances(&genomes, &mut written).unwrap();
+        assert_eq!("genome\tnum_targets\test_counts\ttpm\n\
+                    g1\t2\t40\t700000\n\
+                    g2\t1\t4.5\t300000\n",
+                   String::from_utf8(written).unwrap());
+
+        targets_to_genomes.remove("g2|c1");
+        assert!(abundance_by_genome(&abundances, &targets_to_genomes).is_err());
+        assert!(parse_abundance("target_id\nt\t1\t1\tx\t1\n".as_bytes()).is_err());
+    }
+
+    #[test]
+    fn test_abundance_by_manifest_accession(){
+        let manifest = "accession\tpath\nRS_GCF_000005845.2\ttests/data/genome1.fna\n";
+        let entries = manifest::parse_manifest(manifest.as_bytes(), PathResolution::Direct).unwrap();
+        let genome_files: Vec<(String, String)> = entries.into_iter()
+            .map(GenomeManifestEntry::into_genome_file).collect();
+        let genome_files: Vec<(&str, &str)> = genome_files.iter()
+            .map(|(name, path)| (name.as_str(), path.as_str())).collect();
+
+        let dir = ::tempfile::tempdir().unwrap();
+        let targets_to_genomes_path = dir.path().join(::targets::TARGETS_TO_GENOMES_FILE_NAME);
+        {
+            let mut namer = TargetNamer::new(
+                TargetNaming::GenomeContig, fs::File::create(&targets_to_genomes_path).unwrap()).unwrap();
+            ::write_genome_fasta_files(&genome_files, &mut ::Writer::new(Vec::new()), &mut namer).unwrap();
+        }
+        let targets_to_genomes = ::targets::read_targets_to_genomes(&targets_to_genomes_path).unwrap();
+
+        let abundance = "target_id\tlength\teff_length\test_counts\ttpm\n\
+                         RS_GCF_000005845.2|seq1\t2\t1\t3\t600000\n\
+                         RS_GCF_000005845.2|seq2\t2\t1\t2\t400000\n";
+        let genomes = abundance_by_genome(&parse_abundance(abundance.as_bytes()).unwrap(),
+                                          &targets_to_genomes).unwrap();
+        assert_eq!(vec![GenomeAbundance {
+            genome: "RS_GCF_000005845.2".to_string(), num_targets: 2, est_counts: 5.0, tpm: 1000000.0,
+        }], genomes);
+    }
+}
@@ -0,0 +1,236 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+use manifest::GenomeManifestEntry;
+use taxonomy::normalise_accession;
+
+/// Column of GTDB metadata files giving the species representative of each
+/// genome.
+pub const REPRESENTATIVE_COLUMN: &str = "gtdb_genome_representative";
+
+/// Command line names of the selections other than `PerSpecies`.
+pub const SELECTION_NAMES: [&str; 3] = ["all", "representatives", "non-representatives"];
+
+/// Which genomes of each species cluster are used.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum RepresentativeSelection {
+    All,
+    Representatives,
+    NonRepresentatives,
+    /// Up to this many genomes from each cluster, starting with the
+    /// representative and then in manifest order. Must be at least 1, see
+    /// `per_species`.
+    PerSpecies(usize),
+}
+
+impl RepresentativeSelection {
+    /// Parse the command line representation of a selection other than
+    /// `PerSpecies`.
+    pub fn from_name(name: &str) -> Option<RepresentativeSelection> {
+        match name {
+            "all" => Some(RepresentativeSelection::All),
+            "representatives" => Some(RepresentativeSelection::Representatives),
+            "non-representatives" => Some(RepresentativeSelection::NonRepresentatives),
+            _ => None
+        }
+    }
+
+    /// Select up to the given number of genomes per species, or None if it
+    /// is 0, which would select nothing.
+    pub fn per_species(genomes_per_species: usize) -> Option<RepresentativeSelection> {
+        match genomes_per_species {
+            0 => None,
+            n => Some(RepresentativeSelection::PerSpecies(n)),
+        }
+    }
+}
+
+/// The species cluster of each genome, identified by the accession of its
+/// representative. Accessions are normalised, so GTDB's `RS_` and `GB_`
+/// prefixes are optional throughout.
+#[derive(Debug, Default, PartialEq)]
+pub struct SpeciesClusters {
+    representative_of: HashMap<String, String>,
+}
+
+impl SpeciesClusters {
+    pub fn new() -> SpeciesClusters {
+        SpeciesClusters::default()
+    }
+
+    /// Add a genome to the cluster of the given representative. The
+    /// representative is added to its own cluster.
+    pub fn insert(&mut self, accession: &str, representative: &str) {
+        let representative = normalise_accession(representative).to_string();
+        self.representative_of.entry(representative.clone()).or_insert_with(|| representative.clone());
+        self.representative_of.insert(normalise_accession(accession).to_string(), representative);
+    }
+
+    /// The representative of a genome's cluster, or None if the genome is
+    /// in no cluster.
+    pub fn representative_of(&self, accession: &str) -> Option<&str> {
+        self.representative_of.get(normalise_accession(accession)).map(|r| r.as_str())
+    }
+
+    pub fn is_representative(&self, accession: &str) -> bool {
+        self.representative_of(accession) == Some(normalise_accession(accession))
+    }
+
+    /// Select the manifest entries to use, keeping their order. A genome in
+    /// no cluster is not a representative, and with `PerSpecies` is treated
+    /// as a cluster of its own.
+    pub fn select(&self, entries: Vec<GenomeManifestEntry>, selection: RepresentativeSelection)
+        -> Vec<GenomeManifestEntry> {
+        match selection {
+            RepresentativeSelection::All => entries,
+            RepresentativeSelection::Representatives => entries.into_iter()
+                .filter(|e| self.is_representative(&e.accession))
+                .collect(),
+            RepresentativeSelection::NonRepresentatives => entries.into_iter()
+                .filter(|e| !self.is_representative(&e.accession))
+                .collect(),
+            RepresentativeSelection::PerSpecies(genomes_per_species) => {
+                assert!(genomes_per_species > 0, "Cannot select 0 genomes per species");
+                // Representatives first, so that they are always chosen.
+                let mut chosen = vec![false; entries.len()];
+                let mut cluster_sizes: HashMap<&str, usize> = HashMap::new();
+                let order = (0..entries.len()).filter(|&i| self.is_representative(&entries[i].accession))
+                    .chain((0..entries.len()).filter(|&i| !self.is_representative(&entries[i].accession)));
+                for i in order {
+                    let accession = normalise_accession(&entries[i].accession);
+                    let cluster = self.representative_of(accession).unwrap_or(accession);
+                    let size = cluster_sizes.entry(cluster).or_insert(0);
+                    if *size < genomes_per_species {
+                        *size += 1;
+                        chosen[i] = true;
+                    }
+                }
+                entries.into_iter().zip(chosen).filter(|&(_, c)| c).map(|(e, _)| e).collect()
+            }
+        }
+    }
+}
+
+/// Parse a representatives file with a header line. If the header has a
+/// `gtdb_genome_representative` column, as GTDB metadata files such as
+/// `bac120_metadata.tsv` do, each row gives a genome in its `accession`
+/// column, or else the first column, and the representative of its
+/// cluster. Otherwise the first column of each row is a representative.
+/// Blank lines are skipped.
+pub fn parse_representatives<R: io::BufRead>(reader: R) -> io::Result<SpeciesClusters> {
+    let mut clusters = SpeciesClusters::new();
+    let mut columns = None;
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue
+        }
+        let fields: Vec<&str> = line.split('\t').map(|f| f.trim()).collect();
+        let (accession_column, representative_column) = match columns {
+            Some(columns) => columns,
+            None => {
+                let accession_column = fields.iter().position(|f| *f == "accession").unwrap_or(0);
+                columns = Some((accession_column, fields.iter().position(|f| *f == REPRESENTATIVE_COLUMN)));
+                continue
+            }
+        };
+        let accession = fields.get(accession_column).cloned().unwrap_or("");
+        let representative = match representative_column {
+            Some(column) => fields.get(column).cloned().unwrap_or(""),
+            None => accession,
+        };
+        if accession.is_empty() || representative.is_empty() {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Missing accession or representative on line {} of representatives", i+1)))
+        }
+        clusters.insert(accession, representative);
+    }
+    Ok(clusters)
+}
+
+/// Read a representatives file, as `parse_representatives` does.
+pub fn read_representatives<P: AsRef<Path>>(path: P) -> io::Result<SpeciesClusters> {
+    let file = ::open_with_context(path, "representatives")?;
+    parse_representatives(io::BufReader::new(file))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::PathBuf;
+
+    const METADATA: &str = "accession\tcheckm_completeness\tgtdb_genome_representative\n\
+                            RS_GCF_1\t99.5\tRS_GCF_1\n\
+                            GB_GCA_2\t98.1\tRS_GCF_1\n\
+                            RS_GCF_3\t97.0\tRS_GCF_1\n\
+                            RS_GCF_4\t90.2\tRS_GCF_4\n";
+
+    fn entries(accessions: &[&str]) -> Vec<GenomeManifestEntry> {
+        accessions.iter().map(|a| GenomeManifestEntry {
+            accession: a.to_string(),
+            path: PathBuf::from(format!("{}.fna", a)),
+            taxonomy: None,
+        }).collect()
+    }
+
+    fn accessions(entries: &[GenomeManifestEntry]) -> Vec<&str> {
+        entries.iter().map(|e| e.accession.as_str()).collect()
+    }
+
+    #[test]
+    fn test_parse_representatives(){
+        let clusters = parse_representatives(METADATA.as_bytes()).unwrap();
+        assert_eq!(Some("GCF_1"), clusters.representative_of("GCA_2"));
+        assert!(clusters.is_representative("GCF_4"));
+        assert!(clusters.is_representative("RS_GCF_1"));
+        assert!(!clusters.is_representative("GCF_3"));
+        assert_eq!(None, clusters.representative_of("GCF_5"));
+
+        let list = parse_representatives("accession\tother\nGCF_1\tx\n\nGCF_4\ty\n".as_bytes()).unwrap();
+        assert!(list.is_representative("RS_GCF_1"));
+        assert!(!list.is_representative("GCA_2"));
+
+        let error = parse_representatives("accession\tgtdb_genome_representative\nGCF_1\n".as_bytes())
+            .unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+    }
+
+    #[test]
+    fn test_select(){
+        let clusters = parse_representatives(METADATA.as_bytes()).unwrap();
+        let manifest = || entries(&["GCA_2", "GCF_3", "GCF_1", "GCF_4", "GCF_5"]);
+        assert_eq!(5, clusters.select(manifest(), RepresentativeSelection::All).len());
+        assert_eq!(vec!["GCF_1", "GCF_4"],
+                   accessions(&clusters.select(manifest(), RepresentativeSelection::Representatives)));
+        assert_eq!(vec!["GCA_2", "GCF_3", "GCF_5"],
+                   accessions(&clusters.select(manifest(), RepresentativeSelection::NonRepresentatives)));
+        assert_eq!(vec!["GCF_1", "GCF_4", "GCF_5"],
+                   accessions(&clusters.select(manifest(), RepresentativeSelection::PerSpecies(1))));
+        assert_eq!(vec!["GCA_2", "GCF_1", "GCF_4", "GCF_5"],
+                   accessions(&clusters.select(manifest(), RepresentativeSelection::PerSpecies(2))));
+    }
+
+    #[test]
+    fn test_from_name(){
+        for name in &SELECTION_NAMES {
+            assert!(RepresentativeSelection::from_name(name).is_some());
+        }
+        assert_eq!(None, RepresentativeSelection::from_name("per-species"));
+    }
+
+    #[test]
+    fn test_per_species(){
+        assert_eq!(Some(RepresentativeSelection::PerSpecies(3)), RepresentativeSelection::per_species(3));
+        assert_eq!(None, RepresentativeSelection::per_species(0));
+    }
+
+    #[test]
+    #[should_panic(expected = "Cannot select 0 genomes per species")]
+    fn test_select_zero_per_species(){
+        let clusters = parse_representatives(METADATA.as_bytes()).unwrap();
+        clusters.select(entries(&["GCF_1"]), RepresentativeSelection::PerSpecies(0));
+    }
+}
@@ -0,0 +1,208 @@
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use genomes_and_contigs::KmerMap;
+use kmer::Kmer;
+
+/// Name of the long-form table listing every metric for each pair of
+/// genomes.
+pub const PAIRWISE_FILE_NAME: &str = "similarity.tsv";
+
+/// The measures of similarity between the k-mers of two genomes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SimilarityMetric {
+    /// Shared distinct k-mers over distinct k-mers in either genome.
+    Jaccard,
+    /// Distinct k-mers of the first genome which are also in the second,
+    /// over distinct k-mers of the first genome. Not symmetric.
+    Containment,
+    /// Jaccard similarity counting multiplicity: the sum over k-mers of the
+    /// lesser count over the sum of the greater count.
+    Weighted,
+}
+
+impl SimilarityMetric {
+    pub fn all() -> [SimilarityMetric; 3] {
+        [SimilarityMetric::Jaccard, SimilarityMetric::Containment, SimilarityMetric::Weighted]
+    }
+
+    pub fn name(&self) -> &'static str {
+        match *self {
+            SimilarityMetric::Jaccard => "jaccard",
+            SimilarityMetric::Containment => "containment",
+            SimilarityMetric::Weighted => "weighted",
+        }
+    }
+}
+
+/// Totals from which each similarity metric is calculated for every pair of
+/// genomes.
+#[derive(Debug)]
+pub struct Similarities {
+    pub genomes: Vec<String>,
+    /// Number of distinct k-mers in each genome.
+    distinct: Vec<u64>,
+    /// Total k-mer count of each genome.
+    totals: Vec<u64>,
+    /// Number of distinct k-mers shared by each pair of genomes.
+    shared: Vec<Vec<u64>>,
+    /// Sum over shared k-mers of the lesser count of each pair of genomes.
+    shared_min_counts: Vec<Vec<u64>>,
+}
+
+impl Similarities {
+    /// Tally the k-mers shared between each pair of genomes in a single pass
+    /// over the map.
+    pub fn from_kmer_map<T: Kmer>(kmer_map: &KmerMap<T>) -> Similarities {
+        let n = kmer_map.genomes.len();
+        let mut similarities = Similarities {
+            genomes: kmer_map.genomes.clone(),
+            distinct: vec![0; n],
+            totals: vec![0; n],
+            shared: vec![vec![0; n]; n],
+            shared_min_counts: vec![vec![0; n]; n],
+        };
+        let mut present = Vec::with_capacity(n);
+        for counts in kmer_map.kmers.values() {
+            present.clear();
+            present.extend((0..n).filter(|i| counts[*i] > 0));
+            for (a, i) in present.iter().enumerate() {
+                similarities.distinct[*i] += 1;
+                similarities.totals[*i] += counts[*i] as u64;
+                for j in &present[a+1..] {
+                    let min_count = ::std::cmp::min(counts[*i], counts[*j]) as u64;
+                    similarities.shared[*i][*j] += 1;
+                    similarities.shared[*j][*i] += 1;
+                    similarities.shared_min_counts[*i][*j] += min_count;
+                    similarities.shared_min_counts[*j][*i] += min_count;
+                }
+            }
+        }
+        similarities
+    }
+
+    /// Return the similarity of genome i to genome j. A genome is fully
+    /// similar to itself, and the similarity to or of a genome with no
+    /// k-mers is 0.
+    pub fn similarity(&self, metric: SimilarityMetric, i: usize, j: usize) -> f64 {
+        if i == j {
+            return if self.distinct[i] > 0 { 1.0 } else { 0.0 }
+        }
+        let (numerator, denominator) = match metric {
+            SimilarityMetric::Jaccard => (
+                self.shared[i][j],
+                self.distinct[i] + self.distinct[j] - self.shared[i][j]),
+            SimilarityMetric::Containment => (self.shared[i][j], self.distinct[i]),
+            SimilarityMetric::Weighted => (
+                self.shared_min_counts[i][j],
+                self.totals[i] + self.totals[j] - self.shared_min_counts[i][j]),
+        };
+        if denominator == 0 { 0.0 } else { numerator as f64 / denominator as f64 }
+    }
+
+    /// Write the square matrix of a metric as TSV, with genome names as the
+    /// first row and column. Each row gives the similarity of that genome
+    /// to the genome of each column.
+    pub fn write_matrix<W: io::Write>(&self, metric: SimilarityMetric, writer: W) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(writer);
+        write!(writer, "genome")?;
+        for genome in &self.genomes {
+            write!(writer, "\t{}", genome)?;
+        }
+        writeln!(writer)?;
+        for (i, genome) in self.genomes.iter().enumerate() {
+            write!(writer, "{}", genome)?;
+            for j in 0..self.genomes.len() {
+                write!(writer, "\t{}", self.similarity(metric, i, j))?;
+            }
+            writeln!(writer)?;
+        }
+        writer.flush()
+    }
+
+    /// Write every metric for each ordered pair of distinct genomes, one
+    /// pair per row.
+    pub fn write_pairwise<W: io::Write>(&self, writer: W) -> io::Result<()> {
+        let mut writer = io::BufWriter::new(writer);
+        write!(writer, "genome1\tgenome2\tshared_kmers")?;
+        for metric in SimilarityMetric::all().iter() {
+            write!(writer, "\t{}", metric.name())?;
+        }
+        writeln!(writer)?;
+        for i in 0..self.genomes.len() {
+            for j in 0..self.genomes.len() {
+                if i == j {
+                    continue
+                }
+                write!(writer, "{}\t{}\t{}", self.genomes[i], self.genomes[j], self.shared[i][j])?;
+                for metric in SimilarityMetric::all().iter() {
+                    write!(writer, "\t{}", self.similarity(*metric, i, j))?;
+                }
+                writeln!(writer)?;
+            }
+        }
+        writer.flush()
+    }
+
+    /// Write a `<metric>.tsv` matrix for each metric and the long-form
+    /// table into the output directory.
+    pub fn write_all<P: AsRef<Path>>(&self, output_directory: P) -> io::Result<()> {
+        let output_directory = output_directory.as_ref();
+        fs::create_dir_all(output_directory)?;
+        for metric in SimilarityMetric::all().iter() {
+            let path = output_directory.join(format!("{}.tsv", metric.name()));
+            self.write_matrix(*metric, fs::File::create(path)?)?;
+        }
+        self.write_pairwise(fs::File::create(output_directory.join(PAIRWISE_FILE_NAME))?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use genomes_and_contigs::GenomesAndContigs;
+
+    fn similarities() -> Similarities {
+        let mut genomes_and_contigs = GenomesAndContigs::new();
+        genomes_and_contigs.establish_genome("g1".to_string());
+        genomes_and_contigs.insert_with_sequence("c1".to_string(), b"AAAAC".to_vec());
+        genomes_and_contigs.establish_genome("g2".to_string());
+        genomes_and_contigs.insert_with_sequence("c2".to_string(), b"AAAG".to_vec());
+        genomes_and_contigs.establish_genome("g3".to_string());
+        genomes_and_contigs.insert_with_sequence("c3".to_string(), b"NN".to_vec());
+        Similarities::from_kmer_map(&genomes_and_contigs.establish_kmers::<u64>(3, false))
+    }
+
+    #[test]
+    fn test_similarity(){
+        // g1 has AAA twice and AAC, g2 has AAA and AAG.
+        let similarities = similarities();
+        assert_eq!(1.0 / 3.0, similarities.similarity(SimilarityMetric::Jaccard, 0, 1));
+        assert_eq!(0.5, similarities.similarity(SimilarityMetric::Containment, 0, 1));
+        assert_eq!(0.25, similarities.similarity(SimilarityMetric::Weighted, 0, 1));
+        assert_eq!(1.0, similarities.similarity(SimilarityMetric::Jaccard, 1, 1));
+        assert_eq!(0.0, similarities.similarity(SimilarityMetric::Containment, 2, 0));
+        assert_eq!(0.0, similarities.similarity(SimilarityMetric::Jaccard, 2, 2));
+    }
+
+    #[test]
+    fn test_write_similarities(){
+        let similarities = similarities();
+        let mut matrix = Vec::new();
+        similarities.write_matrix(SimilarityMetric::Containment, &mut matrix).unwrap();
+        assert_eq!(
+            "genome\tg1\tg2\tg3\ng1\t1\t0.5\t0\ng2\t0.5\t1\t0\ng3\t0\t0\t0\n",
+            String::from_utf8(matrix).unwrap());
+
+        let dir = ::tempfile::tempdir().unwrap();
+        similarities.write_all(dir.path()).unwrap();
+        let pairwise = fs::read_to_string(dir.path().join(PAIRWISE_FILE_NAME)).unwrap();
+        let lines: Vec<&str> = pairwise.lines().collect();
+        assert_eq!(7, lines.len());
+        assert_eq!("genome1\tgenome2\tshared_kmers\tjaccard\tcontainment\tweighted", lines[0]);
+        assert_eq!("g1\tg2\t1\t0.3333333333333333\t0.5\t0.25", lines[1]);
+        assert!(dir.path().join("weighted.tsv").exists());
+    }
+}
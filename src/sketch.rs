@@ -0,0 +1,396 @@
+use std::collections::BTreeSet;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+use genomes_and_contigs::GenomesAndContigs;
+use invalid_data;
+use kmer::{Kmer, Kmers};
+
+/// First bytes of a sketch file.
+pub const SKETCH_MAGIC: &[u8; 4] = b"KMSK";
+/// Version of the sketch file layout written by `write_sketches`.
+pub const SKETCH_VERSION: u8 = 1;
+
+/// How the hashes kept in a sketch are chosen.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SketchKind {
+    /// MinHash keeping the given number of smallest hashes, as Mash does.
+    BottomK(u64),
+    /// FracMinHash keeping every hash below the maximum hash divided by the
+    /// given scale, so about one in scale k-mers.
+    Scaled(u64),
+}
+
+/// Parameters shared by sketches which can be compared.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SketchParameters {
+    pub kmer_size: usize,
+    /// Whether each k-mer was hashed together with its reverse complement.
+    pub canonical: bool,
+    pub seed: u64,
+    pub kind: SketchKind,
+}
+
+/// The hashes kept from the k-mers of a genome, in ascending order.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Sketch {
+    pub name: String,
+    pub parameters: SketchParameters,
+    pub hashes: Vec<u64>,
+}
+
+/// Estimates of the similarity between two sketched genomes.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SketchComparison {
+    pub jaccard: f64,
+    /// Estimated fraction of the first genome's k-mers found in the second.
+    pub containment: f64,
+    /// Mash distance, estimating the per-base mutation rate from the Jaccard
+    /// similarity.
+    pub mash_distance: f64,
+    /// Average nucleotide identity implied by the Mash distance.
+    pub ani: f64,
+}
+
+/// Hash a packed k-mer, mixing both halves with the finaliser of
+/// MurmurHash3 so that hashes are uniform regardless of the k-mer size.
+pub fn hash_kmer(kmer: u128, seed: u64) -> u64 {
+    fn mix(mut h: u64) -> u64 {
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xff51afd7ed558ccd);
+        h ^= h >> 33;
+        h = h.wrapping_mul(0xc4ceb9fe1a85ec53);
+        h ^ (h >> 33)
+    }
+    mix(mix(kmer as u64 ^ seed) ^ (kmer >> 64) as u64)
+}
+
+impl Sketch {
+    /// Sketch the contigs of the genome at the given index.
+    pub fn from_genome(genomes_and_contigs: &GenomesAndContigs, genome_index: usize,
+                       parameters: SketchParameters) -> Sketch {
+        if parameters.kmer_size <= u64::MAX_K {
+            Sketch::from_genome_as::<u64>(genomes_and_contigs, genome_index, parameters)
+        } else {
+            Sketch::from_genome_as::<u128>(genomes_and_contigs, genome_index, parameters)
+        }
+    }
+
+    fn from_genome_as<T: Kmer>(genomes_and_contigs: &GenomesAndContigs, genome_index: usize,
+                               parameters: SketchParameters) -> Sketch {
+        let mut hashes = BTreeSet::new();
+        let contig_indices = genomes_and_contigs.contig_indices_of_genome(genome_index)
+            .expect("Genome index out of range");
+        for contig_index in contig_indices {
+            let sequence = &genomes_and_contigs.sequences[*contig_index];
+            for kmer in Kmers::<T>::new(sequence, parameters.kmer_size, parameters.canonical) {
+                let hash = hash_kmer(kmer.to_u128(), parameters.seed);
+                match parameters.kind {
+                    SketchKind::BottomK(size) => {
+                        if (hashes.len() as u64) < size {
+                            hashes.insert(hash);
+                        } else if hash < *hashes.iter().next_back().unwrap() && hashes.insert(hash) {
+                            let largest = *hashes.iter().next_back().unwrap();
+                            hashes.remove(&largest);
+                        }
+                    },
+                    SketchKind::Scaled(scaled) => {
+                        if hash <= u64::MAX / scaled {
+                            hashes.insert(hash);
+                        }
+                    },
+                }
+            }
+        }
+        Sketch {
+            name: genomes_and_contigs.genomes[genome_index].clone(),
+            parameters,
+            hashes: hashes.into_iter().collect(),
+        }
+    }
+
+    /// Estimate the similarity of this genome to another. The sketches must
+    /// have been made with the same parameters.
+    pub fn compare(&self, other: &Sketch) -> io::Result<SketchComparison> {
+        if self.parameters != other.parameters {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!("Sketches of {} and {} were made with different parameters",
+                        self.name, other.name)))
+        }
+        let (jaccard, containment) = match self.parameters.kind {
+            SketchKind::BottomK(size) => {
+                // Only hashes below the largest of both sketches are known
+                // to be absent from the other when missing.
+                let threshold = match (self.hashes.last(), other.hashes.last()) {
+                    (Some(a), Some(b)) => ::std::cmp::min(*a, *b),
+                    _ => 0,
+                };
+                let own = self.hashes.iter().filter(|h| **h <= threshold).count();
+                let shared_below = count_shared(&self.hashes, &other.hashes, threshold);
+                let (shared, union) = bottom_k_union(&self.hashes, &other.hashes, size as usize);
+                (ratio(shared, union), ratio(shared_below, own))
+            },
+            SketchKind::Scaled(_) => {
+                let shared = count_shared(&self.hashes, &other.hashes, u64::MAX);
+                (ratio(shared, self.hashes.len() + other.hashes.len() - shared),
+                 ratio(shared, self.hashes.len()))
+            },
+        };
+        let mash_distance = mash_distance(jaccard, self.parameters.kmer_size);
+        Ok(SketchComparison {
+            jaccard,
+            containment,
+            mash_distance,
+            ani: 1.0 - mash_distance,
+        })
+    }
+}
+
+fn ratio(numerator: usize, denominator: usize) -> f64 {
+    if denominator == 0 { 0.0 } else { numerator as f64 / denominator as f64 }
+}
+
+/// Count the hashes up to the threshold found in both sorted lists.
+fn count_shared(a: &[u64], b: &[u64], threshold: u64) -> usize {
+    let (mut i, mut j, mut shared) = (0, 0, 0);
+    while i < a.len() && j < b.len() && a[i] <= threshold && b[j] <= threshold {
+        if a[i] < b[j] {
+            i += 1;
+        } else if a[i] > b[j] {
+            j += 1;
+        } else {
+            shared += 1;
+            i += 1;
+            j += 1;
+        }
+    }
+    shared
+}
+
+/// Merge two sorted bottom-k sketches into the bottom-k sketch of their
+/// union, returning how many of its hashes are in both and its size.
+fn bottom_k_union(a: &[u64], b: &[u64], size: usize) -> (usize, usize) {
+    let (mut i, mut j, mut shared, mut union) = (0, 0, 0, 0);
+    while union < size && (i < a.len() || j < b.len()) {
+        if j == b.len() || (i < a.len() && a[i] < b[j]) {
+            i += 1;
+        } else if i == a.len() || a[i] > b[j] {
+            j += 1;
+        } else {
+            shared += 1;
+            i += 1;
+            j += 1;
+        }
+        union += 1;
+    }
+    (shared, union)
+}
+
+/// The Mash distance `-1/k ln(2j / (1 + j))` for a Jaccard similarity j,
+/// which is 1 when nothing is shared.
+pub fn mash_distance(jaccard: f64, kmer_size: usize) -> f64 {
+    if jaccard <= 0.0 {
+        return 1.0
+    } else if jaccard >= 1.0 {
+        return 0.0
+    }
+    let distance = -1.0 / kmer_size as f64 * (2.0 * jaccard / (1.0 + jaccard)).ln();
+    if distance > 1.0 { 1.0 } else { distance }
+}
+
+/// Sketch each genome, given as `(name, path)`, reading one file at a time
+/// so that only one genome is held in memory. Sketches take the name of
+/// their genome.
+pub fn sketch_genome_fasta_files(genome_files: &[(&str, &str)], parameters: SketchParameters)
+    -> io::Result<Vec<Sketch>> {
+    genome_files.iter()
+        .map(|genome_file| Ok(Sketch::from_genome(
+            &::read_named_genome_fasta_files(&[*genome_file])?, 0, parameters)))
+        .collect()
+}
+
+/// Write sketches in a binary file. All integers are little-endian. After
+/// the magic bytes `KMSK`, a version byte and a u32 number of sketches, each
+/// sketch is its name as a u32 length and UTF-8 bytes, the k-mer size byte,
+/// a byte which is 1 if k-mers are canonical, the u64 seed, a kind byte of 0
+/// for bottom-k or 1 for scaled, the u64 sketch size or scale, and a u32
+/// number of u64 hashes followed by the hashes.
+pub fn write_sketches<W: io::Write>(sketches: &[Sketch], writer: W) -> io::Result<()> {
+    let mut writer = io::BufWriter::new(writer);
+    writer.write_all(SKETCH_MAGIC)?;
+    writer.write_all(&[SKETCH_VERSION])?;
+    writer.write_all(&(sketches.len() as u32).to_le_bytes())?;
+    for sketch in sketches {
+        let parameters = &sketch.parameters;
+        writer.write_all(&(sketch.name.len() as u32).to_le_bytes())?;
+        writer.write_all(sketch.name.as_bytes())?;
+        writer.write_all(&[parameters.kmer_size as u8, parameters.canonical as u8])?;
+        writer.write_all(&parameters.seed.to_le_bytes())?;
+        let (kind, value) = match parameters.kind {
+            SketchKind::BottomK(size) => (0u8, size),
+            SketchKind::Scaled(scaled) => (1u8, scaled),
+        };
+        writer.write_all(&[kind])?;
+        writer.write_all(&value.to_le_bytes())?;
+        writer.write_all(&(sketch.hashes.len() as u32).to_le_bytes())?;
+        for hash in &sketch.hashes {
+            writer.write_all(&hash.to_le_bytes())?;
+        }
+    }
+    writer.flush()
+}
+
+fn read_u8<R: io::Read>(reader: &mut R) -> io::Result<u8> {
+    let mut bytes = [0u8; 1];
+    reader.read_exact(&mut bytes)?;
+    Ok(bytes[0])
+}
+
+fn read_u32<R: io::Read>(reader: &mut R) -> io::Result<u32> {
+    let mut bytes = [0u8; 4];
+    reader.read_exact(&mut bytes)?;
+    Ok(u32::from_le_bytes(bytes))
+}
+
+fn read_u64<R: io::Read>(reader: &mut R) -> io::Result<u64> {
+    let mut bytes = [0u8; 8];
+    reader.read_exact(&mut bytes)?;
+    Ok(u64::from_le_bytes(bytes))
+}
+
+/// Read sketches written by `write_sketches`.
+pub fn read_sketches<R: io::Read>(reader: R) -> io::Result<Vec<Sketch>> {
+    let mut reader = io::BufReader::new(reader);
+    let mut magic = [0u8; 4];
+    reader.read_exact(&mut magic)?;
+    if &magic != SKETCH_MAGIC {
+        return Err(invalid_data("Not a sketch file".to_string()))
+    }
+    let version = read_u8(&mut reader)?;
+    if version != SKETCH_VERSION {
+        return Err(invalid_data(format!("Unsupported sketch file version {}", version)))
+    }
+    let num_sketches = read_u32(&mut reader)?;
+    let mut sketches = vec!();
+    for _ in 0..num_sketches {
+        let mut name = vec![0u8; read_u32(&mut reader)? as usize];
+        reader.read_exact(&mut name)?;
+        let kmer_size = read_u8(&mut reader)? as usize;
+        let canonical = read_u8(&mut reader)? == 1;
+        let seed = read_u64(&mut reader)?;
+        let kind = match (read_u8(&mut reader)?, read_u64(&mut reader)?) {
+            (0, 0) => return Err(invalid_data("Sketch size must be at least 1".to_string())),
+            (1, 0) => return Err(invalid_data("Sketch scale must be at least 1".to_string())),
+            (0, size) => SketchKind::BottomK(size),
+            (1, scaled) => SketchKind::Scaled(scaled),
+            (kind, _) => return Err(invalid_data(format!("Unknown sketch kind {}", kind))),
+        };
+        let num_hashes = read_u32(&mut reader)?;
+        let mut hashes = Vec::with_capacity(num_hashes as usize);
+        for _ in 0..num_hashes {
+            hashes.push(read_u64(&mut reader)?);
+        }
+        sketches.push(Sketch {
+            name: String::from_utf8(name).map_err(|e| invalid_data(e.to_string()))?,
+            parameters: SketchParameters {
+                kmer_size,
+                canonical,
+                seed,
+                kind,
+            },
+            hashes,
+        });
+    }
+    Ok(sketches)
+}
+
+/// Read the sketches in a sketch file.
+pub fn read_sketch_file<P: AsRef<Path>>(path: P) -> io::Result<Vec<Sketch>> {
+    read_sketches(fs::File::open(path)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn genomes() -> GenomesAndContigs {
+        let mut genomes_and_contigs = GenomesAndContigs::new();
+        let sequence = b"GATTACAGATCCGATAGGCTTACGATCGGGCATCGATTTACGACTAGCATCAGACGGCGCATTCAG";
+        genomes_and_contigs.establish_genome("g1".to_string());
+        genomes_and_contigs.insert_with_sequence("c1".to_string(), sequence.to_vec());
+        genomes_and_contigs.establish_genome("g2".to_string());
+        genomes_and_contigs.insert_with_sequence("c2".to_string(), sequence[..40].to_vec());
+        genomes_and_contigs
+    }
+
+    fn parameters(kind: SketchKind) -> SketchParameters {
+        SketchParameters { kmer_size: 11, canonical: true, seed: 42, kind }
+    }
+
+    #[test]
+    fn test_bottom_k_sketch(){
+        let genomes = genomes();
+        let sketch = Sketch::from_genome(&genomes, 0, parameters(SketchKind::BottomK(10)));
+        assert_eq!(10, sketch.hashes.len());
+        let mut all: Vec<u64> = Kmers::<u64>::new(&genomes.sequences[0], 11, true)
+            .map(|k| hash_kmer(k as u128, 42)).collect();
+        all.sort();
+        all.dedup();
+        assert_eq!(&all[..10], &sketch.hashes[..]);
+
+        let comparison = sketch.compare(&sketch).unwrap();
+        assert_eq!(1.0, comparison.jaccard);
+        assert_eq!(1.0, comparison.ani);
+
+        let other = Sketch::from_genome(&genomes, 1, parameters(SketchKind::BottomK(1000)));
+        let scaled = Sketch::from_genome(&genomes, 1, parameters(SketchKind::Scaled(2)));
+        assert!(sketch.compare(&other).is_err());
+        assert!(sketch.compare(&scaled).is_err());
+    }
+
+    #[test]
+    fn test_scaled_sketch(){
+        let genomes = genomes();
+        let all = Sketch::from_genome(&genomes, 0, parameters(SketchKind::Scaled(1)));
+        let part = Sketch::from_genome(&genomes, 1, parameters(SketchKind::Scaled(1)));
+        assert_eq!(56, all.hashes.len());
+        assert_eq!(30, part.hashes.len());
+        let comparison = part.compare(&all).unwrap();
+        assert_eq!(1.0, comparison.containment);
+        assert_eq!(30.0 / 56.0, comparison.jaccard);
+        assert_eq!(30.0 / 56.0, all.compare(&part).unwrap().containment);
+        assert!(comparison.ani < 1.0 && comparison.ani > 0.9);
+    }
+
+    #[test]
+    fn test_mash_distance(){
+        assert_eq!(0.0, mash_distance(1.0, 21));
+        assert_eq!(1.0, mash_distance(0.0, 21));
+    }
+
+    #[test]
+    fn test_sketch_round_trip(){
+        let sketches = vec![
+            Sketch::from_genome(&genomes(), 0, parameters(SketchKind::BottomK(5))),
+            Sketch::from_genome(&genomes(), 1, parameters(SketchKind::Scaled(4))),
+        ];
+        let mut bytes = Vec::new();
+        write_sketches(&sketches, &mut bytes).unwrap();
+        assert_eq!(sketches, read_sketches(&bytes[..]).unwrap());
+        assert!(read_sketches(&b"KMSK\x02"[..]).is_err());
+    }
+
+    #[test]
+    fn test_read_sketches_rejects_empty_kinds(){
+        for kind in &[SketchKind::BottomK(0), SketchKind::Scaled(0)] {
+            let sketch = Sketch { name: "g".to_string(), parameters: parameters(*kind), hashes: vec!() };
+            let mut bytes = Vec::new();
+            write_sketches(&[sketch], &mut bytes).unwrap();
+            let error = read_sketches(&bytes[..]).unwrap_err();
+            assert!(error.to_string().contains("must be at least 1"));
+        }
+    }
+}
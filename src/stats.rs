@@ -0,0 +1,284 @@
+use std::io;
+
+use genomes_and_contigs::GenomesAndContigs;
+
+/// Format of written assembly statistics.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum StatsFormat {
+    Tsv,
+    /// An array of one object per genome or contig.
+    Json,
+}
+
+impl StatsFormat {
+    /// Parse the command line representation of a format.
+    pub fn from_name(name: &str) -> Option<StatsFormat> {
+        match name {
+            "tsv" => Some(StatsFormat::Tsv),
+            "json" => Some(StatsFormat::Json),
+            _ => None
+        }
+    }
+}
+
+/// Base composition of a sequence.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct BaseCounts {
+    length: u64,
+    gc: u64,
+    at: u64,
+    n: u64,
+}
+
+impl BaseCounts {
+    fn of(sequence: &[u8]) -> BaseCounts {
+        let mut counts = BaseCounts { length: sequence.len() as u64, ..Default::default() };
+        for base in sequence {
+            match base.to_ascii_uppercase() {
+                b'G' | b'C' => counts.gc += 1,
+                b'A' | b'T' => counts.at += 1,
+                b'N' => counts.n += 1,
+                _ => {}
+            }
+        }
+        counts
+    }
+
+    fn add(&mut self, other: &BaseCounts) {
+        self.length += other.length;
+        self.gc += other.gc;
+        self.at += other.at;
+        self.n += other.n;
+    }
+
+    /// Fraction of unambiguous bases which are G or C, or 0 if there are
+    /// none.
+    fn gc_content(&self) -> f64 {
+        match self.gc + self.at {
+            0 => 0.0,
+            acgt => self.gc as f64 / acgt as f64,
+        }
+    }
+
+    fn n_fraction(&self) -> f64 {
+        match self.length {
+            0 => 0.0,
+            length => self.n as f64 / length as f64,
+        }
+    }
+}
+
+/// Statistics of one contig.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ContigStats {
+    pub genome: String,
+    pub contig: String,
+    pub length: u64,
+    pub gc_content: f64,
+    pub n_fraction: f64,
+}
+
+/// Statistics of the assembly of one genome.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenomeStats {
+    pub genome: String,
+    pub total_length: u64,
+    pub num_contigs: usize,
+    pub n50: u64,
+    pub l50: usize,
+    pub gc_content: f64,
+    pub n_fraction: f64,
+    pub longest_contig: u64,
+}
+
+/// Return the N50 and L50 of a set of contig lengths: the length of the
+/// shortest contig among the fewest longest contigs which make up at least
+/// half the total length, and the number of those contigs. Both are 0 if
+/// there are no contigs.
+pub fn n50_l50(lengths: &[u64]) -> (u64, usize) {
+    let mut sorted = lengths.to_vec();
+    sorted.sort_by(|a, b| b.cmp(a));
+    let total: u64 = sorted.iter().sum();
+    let mut cumulative = 0;
+    for (i, length) in sorted.iter().enumerate() {
+        cumulative += length;
+        if 2 * cumulative >= total {
+            return (*length, i + 1)
+        }
+    }
+    (0, 0)
+}
+
+/// Return the statistics of each contig of a genome, in insertion order.
+pub fn contig_stats(genomes_and_contigs: &GenomesAndContigs, genome_index: usize) -> Vec<ContigStats> {
+    let genome = &genomes_and_contigs.genomes[genome_index];
+    genomes_and_contigs.contig_indices_of_genome(genome_index).unwrap().iter().map(|&i| {
+        let counts = BaseCounts::of(&genomes_and_contigs.sequences[i]);
+        ContigStats {
+            genome: genome.clone(),
+            contig: genomes_and_contigs.contigs[i].clone(),
+            length: counts.length,
+            gc_content: counts.gc_content(),
+            n_fraction: counts.n_fraction(),
+        }
+    }).collect()
+}
+
+/// Return the statistics of a genome's assembly.
+pub fn genome_stats(genomes_and_contigs: &GenomesAndContigs, genome_index: usize) -> GenomeStats {
+    let mut total = BaseCounts::default();
+    let mut lengths = vec!();
+    for &i in genomes_and_contigs.contig_indices_of_genome(genome_index).unwrap() {
+        let counts = BaseCounts::of(&genomes_and_contigs.sequences[i]);
+        total.add(&counts);
+        lengths.push(counts.length);
+    }
+    let (n50, l50) = n50_l50(&lengths);
+    GenomeStats {
+        genome: genomes_and_contigs.genomes[genome_index].clone(),
+        total_length: total.length,
+        num_contigs: lengths.len(),
+        n50,
+        l50,
+        gc_content: total.gc_content(),
+        n_fraction: total.n_fraction(),
+        longest_contig: lengths.iter().cloned().max().unwrap_or(0),
+    }
+}
+
+fn json_string(value: &str) -> String {
+    let mut escaped = String::with_capacity(value.len() + 2);
+    escaped.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => escaped.push_str("\\\""),
+            '\\' => escaped.push_str("\\\\"),
+            '\n' => escaped.push_str("\\n"),
+            '\t' => escaped.push_str("\\t"),
+            c if (c as u32) < 0x20 => escaped.push_str(&format!("\\u{:04x}", c as u32)),
+            c => escaped.push(c),
+        }
+    }
+    escaped.push('"');
+    escaped
+}
+
+/// Write rows of named fields, each already formatted as TSV and JSON
+/// values.
+fn write_rows<W: io::Write>(header: &[&str], rows: &[Vec<(String, String)>],
+                            format: StatsFormat, mut writer: W) -> io::Result<()> {
+    match format {
+        StatsFormat::Tsv => {
+            writeln!(writer, "{}", header.join("\t"))?;
+            for row in rows {
+                let fields: Vec<&str> = row.iter().map(|f| f.0.as_str()).collect();
+                writeln!(writer, "{}", fields.join("\t"))?;
+            }
+        },
+        StatsFormat::Json => {
+            writeln!(writer, "[")?;
+            for (i, row) in rows.iter().enumerate() {
+                let fields: Vec<String> = header.iter().zip(row)
+                    .map(|(name, field)| format!("{}: {}", json_string(name), field.1))
+                    .collect();
+                writeln!(writer, "  {{{}}}{}", fields.join(", "),
+                         if i + 1 < rows.len() { "," } else { "" })?;
+            }
+            writeln!(writer, "]")?;
+        }
+    }
+    writer.flush()
+}
+
+fn field<T: ToString>(value: T) -> (String, String) {
+    let value = value.to_string();
+    (value.clone(), value)
+}
+
+fn string_field(value: &str) -> (String, String) {
+    (value.to_string(), json_string(value))
+}
+
+fn fraction_field(value: f64) -> (String, String) {
+    field(format!("{:.4}", value))
+}
+
+/// Write genome statistics with a header line or as JSON. GC content and N
+/// fraction are given to 4 decimal places.
+pub fn write_genome_stats<W: io::Write>(stats: &[GenomeStats], format: StatsFormat, writer: W)
+    -> io::Result<()> {
+    let rows: Vec<Vec<(String, String)>> = stats.iter().map(|s| vec![
+        string_field(&s.genome), field(s.total_length), field(s.num_contigs), field(s.n50),
+        field(s.l50), fraction_field(s.gc_content), fraction_field(s.n_fraction),
+        field(s.longest_contig),
+    ]).collect();
+    write_rows(&["genome", "total_length", "num_contigs", "n50", "l50", "gc_content",
+                 "n_fraction", "longest_contig"], &rows, format, writer)
+}
+
+/// Write contig statistics, as `write_genome_stats` does.
+pub fn write_contig_stats<W: io::Write>(stats: &[ContigStats], format: StatsFormat, writer: W)
+    -> io::Result<()> {
+    let rows: Vec<Vec<(String, String)>> = stats.iter().map(|s| vec![
+        string_field(&s.genome), string_field(&s.contig), field(s.length),
+        fraction_field(s.gc_content), fraction_field(s.n_fraction),
+    ]).collect();
+    write_rows(&["genome", "contig", "length", "gc_content", "n_fraction"], &rows, format, writer)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assembly() -> GenomesAndContigs {
+        let mut genomes_and_contigs = GenomesAndContigs::new();
+        genomes_and_contigs.establish_genome("g1".to_string());
+        genomes_and_contigs.insert_with_sequence("c1".to_string(), b"ACGTACGTGG".to_vec());
+        genomes_and_contigs.insert_with_sequence("c2".to_string(), b"atNN".to_vec());
+        genomes_and_contigs.insert_with_sequence("c3".to_string(), b"GGGCCCAA".to_vec());
+        genomes_and_contigs
+    }
+
+    #[test]
+    fn test_n50_l50(){
+        assert_eq!((8, 2), n50_l50(&[10, 4, 8]));
+        assert_eq!((10, 1), n50_l50(&[10, 10]));
+        assert_eq!((0, 0), n50_l50(&[]));
+    }
+
+    #[test]
+    fn test_genome_stats(){
+        let stats = genome_stats(&assembly(), 0);
+        assert_eq!(22, stats.total_length);
+        assert_eq!(3, stats.num_contigs);
+        assert_eq!((8, 2), (stats.n50, stats.l50));
+        assert_eq!(10, stats.longest_contig);
+        assert_eq!(12.0 / 20.0, stats.gc_content);
+        assert_eq!(2.0 / 22.0, stats.n_fraction);
+
+        let mut tsv = Vec::new();
+        write_genome_stats(std::slice::from_ref(&stats), StatsFormat::Tsv, &mut tsv).unwrap();
+        assert_eq!("genome\ttotal_length\tnum_contigs\tn50\tl50\tgc_content\tn_fraction\tlongest_contig\n\
+                    g1\t22\t3\t8\t2\t0.6000\t0.0909\t10\n", String::from_utf8(tsv).unwrap());
+        let mut json = Vec::new();
+        write_genome_stats(&[stats], StatsFormat::Json, &mut json).unwrap();
+        assert_eq!("[\n  {\"genome\": \"g1\", \"total_length\": 22, \"num_contigs\": 3, \"n50\": 8, \
+                    \"l50\": 2, \"gc_content\": 0.6000, \"n_fraction\": 0.0909, \"longest_contig\": 10}\n]\n",
+                   String::from_utf8(json).unwrap());
+    }
+
+    #[test]
+    fn test_contig_stats(){
+        let stats = contig_stats(&assembly(), 0);
+        assert_eq!(vec!["c1", "c2", "c3"], stats.iter().map(|s| s.contig.as_str()).collect::<Vec<_>>());
+        assert_eq!(0.0, stats[1].gc_content);
+        assert_eq!(0.5, stats[1].n_fraction);
+
+        let mut json = Vec::new();
+        write_contig_stats(&stats[..2], StatsFormat::Json, &mut json).unwrap();
+        assert_eq!("[\n  {\"genome\": \"g1\", \"contig\": \"c1\", \"length\": 10, \"gc_content\": 0.6000, \
+                    \"n_fraction\": 0.0000},\n  {\"genome\": \"g1\", \"contig\": \"c2\", \"length\": 4, \
+                    \"gc_content\": 0.0000, \"n_fraction\": 0.5000}\n]\n",
+                   String::from_utf8(json).unwrap());
+    }
+}
@@ -0,0 +1,184 @@
+use std::collections::{HashMap, HashSet};
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+use std::path::Path;
+
+/// Name of the file written next to the kallisto index which maps each
+/// target back to its genome.
+pub const TARGETS_TO_GENOMES_FILE_NAME: &str = "targets_to_genomes.tsv";
+
+/// Separator between genome and contig names in `GenomeContig` naming.
+pub const GENOME_CONTIG_SEPARATOR: &str = "|";
+
+/// How contigs are named when written as targets of an index.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum TargetNaming {
+    /// The contig name as it appears in its FASTA file. Names must be unique
+    /// across all genomes.
+    Contig,
+    /// `<genome>|<contig>`, unique as long as contig names are unique within
+    /// each genome.
+    GenomeContig,
+    /// The index of the target in the order written, starting from 0.
+    Numeric,
+}
+
+impl TargetNaming {
+    /// Parse the command line representation of a naming scheme.
+    pub fn from_name(name: &str) -> Option<TargetNaming> {
+        match name {
+            "contig" => Some(TargetNaming::Contig),
+            "genome-contig" => Some(TargetNaming::GenomeContig),
+            "numeric" => Some(TargetNaming::Numeric),
+            _ => None
+        }
+    }
+
+    /// Return the name of a target.
+    pub fn target_name(&self, genome: &str, contig: &str, target_index: usize) -> String {
+        match *self {
+            TargetNaming::Contig => contig.to_string(),
+            TargetNaming::GenomeContig => format!("{}{}{}", genome, GENOME_CONTIG_SEPARATOR, contig),
+            TargetNaming::Numeric => target_index.to_string(),
+        }
+    }
+}
+
+/// Names targets according to a `TargetNaming` scheme while recording which
+/// genome each belongs to as a TSV with a `target` and `genome` column.
+pub struct TargetNamer<W: io::Write> {
+    naming: TargetNaming,
+    targets_to_genomes: io::BufWriter<W>,
+    num_targets: usize,
+    /// Target names so far, kept only under `Contig` naming, the one scheme
+    /// whose names can clash between genomes.
+    seen_names: HashSet<String>,
+    /// Genomes so far and the contigs of the current one, kept only under
+    /// `GenomeContig` naming. Each genome's contigs are expected together.
+    seen_genomes: HashSet<String>,
+    current_genome: Option<String>,
+    current_contigs: HashSet<String>,
+}
+
+impl<W: io::Write> TargetNamer<W> {
+    /// Create a new namer, writing the header of the mapping immediately.
+    pub fn new(naming: TargetNaming, targets_to_genomes: W) -> io::Result<Self> {
+        let mut targets_to_genomes = io::BufWriter::new(targets_to_genomes);
+        writeln!(targets_to_genomes, "target\tgenome")?;
+        Ok(TargetNamer {
+            naming,
+            targets_to_genomes,
+            num_targets: 0,
+            seen_names: HashSet::new(),
+            seen_genomes: HashSet::new(),
+            current_genome: None,
+            current_contigs: HashSet::new(),
+        })
+    }
+
+    /// Name the next target and record its genome. An error is returned if
+    /// the name has been used before, as happens under `Contig` naming when
+    /// genomes share contig names, or under `GenomeContig` naming when a
+    /// genome repeats a contig name or its name was given to an earlier
+    /// genome.
+    pub fn name_target(&mut self, genome: &str, contig: &str) -> io::Result<String> {
+        let name = self.naming.target_name(genome, contig, self.num_targets);
+        let unique = match self.naming {
+            TargetNaming::Contig => self.seen_names.insert(name.clone()),
+            TargetNaming::GenomeContig => {
+                if self.current_genome.as_deref() != Some(genome) {
+                    if !self.seen_genomes.insert(genome.to_string()) {
+                        return Err(io::Error::new(
+                            io::ErrorKind::InvalidData,
+                            format!("Genome name {} is used by more than one genome, \
+                                     so its target names would not be unique", genome)));
+                    }
+                    self.current_genome = Some(genome.to_string());
+                    self.current_contigs.clear();
+                }
+                self.current_contigs.insert(contig.to_string())
+            },
+            TargetNaming::Numeric => true,
+        };
+        if !unique {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Duplicate target name {} found in genome {}, consider \
+                         using a different target naming scheme", name, genome)));
+        }
+        writeln!(self.targets_to_genomes, "{}\t{}", name, genome)?;
+        self.num_targets += 1;
+        Ok(name)
+    }
+
+    /// Number of targets named so far.
+    pub fn num_targets(&self) -> usize {
+        self.num_targets
+    }
+
+    /// Flush the mapping, ensuring that everything is written.
+    pub fn flush(&mut self) -> io::Result<()> {
+        self.targets_to_genomes.flush()
+    }
+}
+
+/// Read a target to genome mapping as written by `TargetNamer`.
+pub fn read_targets_to_genomes<P: AsRef<Path>>(path: P) -> io::Result<HashMap<String, String>> {
+    let reader = io::BufReader::new(fs::File::open(path)?);
+    let mut targets_to_genomes = HashMap::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        if i == 0 || line.is_empty() {
+            continue
+        }
+        let mut fields = line.splitn(2, '\t');
+        match (fields.next(), fields.next()) {
+            (Some(target), Some(genome)) => {
+                targets_to_genomes.insert(target.to_string(), genome.to_string());
+            },
+            _ => return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Expected target and genome columns on line {} of target mapping", i+1)))
+        }
+    }
+    Ok(targets_to_genomes)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_target_naming(){
+        assert_eq!("c1", TargetNaming::Contig.target_name("g1", "c1", 4));
+        assert_eq!("g1|c1", TargetNaming::GenomeContig.target_name("g1", "c1", 4));
+        assert_eq!("4", TargetNaming::Numeric.target_name("g1", "c1", 4));
+        assert_eq!(Some(TargetNaming::GenomeContig), TargetNaming::from_name("genome-contig"));
+        assert_eq!(None, TargetNaming::from_name("other"));
+    }
+
+    #[test]
+    fn test_target_namer_duplicates(){
+        let mut namer = TargetNamer::new(TargetNaming::Contig, Vec::new()).unwrap();
+        namer.name_target("g1", "contig_1").unwrap();
+        assert!(namer.name_target("g2", "contig_1").is_err());
+
+        let mut namer = TargetNamer::new(TargetNaming::GenomeContig, Vec::new()).unwrap();
+        namer.name_target("g1", "contig_1").unwrap();
+        namer.name_target("g2", "contig_1").unwrap();
+        namer.flush().unwrap();
+        assert_eq!(2, namer.num_targets());
+        assert!(namer.name_target("g2", "contig_1").is_err());
+        assert!(namer.name_target("g1", "contig_2").is_err());
+        assert!(namer.seen_names.is_empty());
+        assert_eq!(
+            "target\tgenome\ng1|contig_1\tg1\ng2|contig_1\tg2\n",
+            String::from_utf8(namer.targets_to_genomes.get_ref().clone()).unwrap());
+
+        let mut namer = TargetNamer::new(TargetNaming::Numeric, Vec::new()).unwrap();
+        namer.name_target("g1", "contig_1").unwrap();
+        namer.name_target("g1", "contig_1").unwrap();
+        assert!(namer.seen_names.is_empty());
+    }
+}
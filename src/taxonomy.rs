@@ -0,0 +1,220 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::Path;
+
+/// Strip the `RS_` or `GB_` prefix which GTDB adds to RefSeq and GenBank
+/// accessions, so that they match the accessions of NCBI downloads.
+pub fn normalise_accession(accession: &str) -> &str {
+    if accession.starts_with("RS_") || accession.starts_with("GB_") {
+        &accession[3..]
+    } else {
+        accession
+    }
+}
+
+/// Split a GTDB lineage such as `d__Bacteria;p__Firmicutes;...` into its
+/// taxa, from domain down. Ranks left unnamed, e.g. `s__`, end the lineage.
+pub fn parse_lineage(lineage: &str) -> Vec<&str> {
+    lineage.split(';')
+        .map(|taxon| taxon.trim())
+        .take_while(|taxon| !taxon.is_empty() && !taxon.ends_with("__"))
+        .collect()
+}
+
+#[derive(Debug)]
+struct Taxon {
+    name: String,
+    parent: Option<usize>,
+}
+
+/// The taxa of a set of genomes, with the genomes placed at the most
+/// specific taxon of their lineage. Taxa are identified by their GTDB name
+/// including the rank prefix, e.g. `g__Escherichia`.
+#[derive(Debug, Default)]
+pub struct TaxonomyTree {
+    taxa: Vec<Taxon>,
+    taxon_indices: HashMap<String, usize>,
+    /// Index of the most specific taxon of each genome, by normalised
+    /// accession.
+    genomes: HashMap<String, usize>,
+}
+
+impl TaxonomyTree {
+    pub fn new() -> TaxonomyTree {
+        TaxonomyTree::default()
+    }
+
+    /// Number of genomes in the tree.
+    pub fn len(&self) -> usize {
+        self.genomes.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.genomes.is_empty()
+    }
+
+    /// Add a genome with the given lineage string. Returns an error if a
+    /// taxon of the lineage is already in the tree under a different
+    /// parent.
+    pub fn insert(&mut self, accession: &str, lineage: &str) -> Result<(), String> {
+        let mut parent = None;
+        for name in parse_lineage(lineage) {
+            let index = match self.taxon_indices.get(name) {
+                Some(&index) => {
+                    if self.taxa[index].parent != parent {
+                        return Err(format!(
+                            "taxon {} has more than one parent: {} and {}", name,
+                            self.taxon_name(self.taxa[index].parent),
+                            self.taxon_name(parent)))
+                    }
+                    index
+                },
+                None => {
+                    self.taxa.push(Taxon { name: name.to_string(), parent });
+                    self.taxon_indices.insert(name.to_string(), self.taxa.len() - 1);
+                    self.taxa.len() - 1
+                }
+            };
+            parent = Some(index);
+        }
+        match parent {
+            Some(index) => {
+                self.genomes.insert(normalise_accession(accession).to_string(), index);
+                Ok(())
+            },
+            None => Err(format!("genome {} has an empty lineage", accession)),
+        }
+    }
+
+    fn taxon_name(&self, index: Option<usize>) -> &str {
+        index.map(|i| self.taxa[i].name.as_str()).unwrap_or("the root")
+    }
+
+    /// Whether a taxon of this name is in the tree.
+    pub fn contains_taxon(&self, taxon: &str) -> bool {
+        self.taxon_indices.contains_key(taxon)
+    }
+
+    /// The lineage of a genome from domain down, or None if it is not in
+    /// the tree. The accession may have an `RS_` or `GB_` prefix or not.
+    pub fn lineage(&self, accession: &str) -> Option<Vec<&str>> {
+        self.genomes.get(normalise_accession(accession)).map(|&index| {
+            let mut lineage = vec!();
+            let mut current = Some(index);
+            while let Some(i) = current {
+                lineage.push(self.taxa[i].name.as_str());
+                current = self.taxa[i].parent;
+            }
+            lineage.reverse();
+            lineage
+        })
+    }
+
+    /// Normalised accessions of the genomes within a taxon, sorted.
+    pub fn genomes_in(&self, taxon: &str) -> Vec<&str> {
+        let mut genomes: Vec<&str> = self.genomes.keys()
+            .filter(|accession| self.lineage(accession).unwrap().contains(&taxon))
+            .map(|accession| accession.as_str())
+            .collect();
+        genomes.sort();
+        genomes
+    }
+}
+
+/// Parse a GTDB taxonomy file, e.g. `bac120_taxonomy.tsv`, which has an
+/// accession and a lineage separated by a tab on each line and no header.
+/// Blank lines are skipped. A line without exactly two columns, or placing
+/// a taxon under a different parent than before, fails with its line
+/// number.
+pub fn parse_taxonomy<R: io::BufRead>(reader: R) -> io::Result<TaxonomyTree> {
+    let mut tree = TaxonomyTree::new();
+    for (i, line) in reader.lines().enumerate() {
+        let line = line?;
+        let line = line.trim_end_matches('\r');
+        if line.trim().is_empty() {
+            continue
+        }
+        let fields: Vec<&str> = line.split('\t').collect();
+        if fields.len() != 2 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Expected accession and lineage columns separated by a tab \
+                         on line {} of taxonomy, found {} column(s)",
+                        i+1, fields.len())))
+        }
+        tree.insert(fields[0].trim(), fields[1]).map_err(|e| io::Error::new(
+            io::ErrorKind::InvalidData,
+            format!("Invalid lineage on line {} of taxonomy: {}", i+1, e)))?;
+    }
+    Ok(tree)
+}
+
+/// Read a GTDB taxonomy file, as `parse_taxonomy` does.
+pub fn read_taxonomy<P: AsRef<Path>>(path: P) -> io::Result<TaxonomyTree> {
+    let file = ::open_with_context(path, "taxonomy")?;
+    parse_taxonomy(io::BufReader::new(file))
+}
+
+/// Taxa which a genome's lineage must include at least one of, unless
+/// there are none, and must exclude all of.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct TaxonFilter {
+    pub include: Vec<String>,
+    pub exclude: Vec<String>,
+}
+
+impl TaxonFilter {
+    pub fn is_empty(&self) -> bool {
+        self.include.is_empty() && self.exclude.is_empty()
+    }
+
+    /// Whether a genome with this lineage passes the filter.
+    pub fn matches(&self, lineage: &[&str]) -> bool {
+        (self.include.is_empty() || self.include.iter().any(|t| lineage.contains(&t.as_str())))
+            && !self.exclude.iter().any(|t| lineage.contains(&t.as_str()))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TAXONOMY: &str = "RS_GCF_1\td__Bacteria;p__Proteobacteria;g__Escherichia;s__Escherichia coli\n\
+                            GB_GCA_2\td__Bacteria;p__Proteobacteria;g__Escherichia;s__Escherichia fergusonii\n\
+                            \n\
+                            RS_GCF_3\td__Bacteria;p__Firmicutes;g__Bacillus;s__\n";
+
+    #[test]
+    fn test_parse_taxonomy(){
+        let tree = parse_taxonomy(TAXONOMY.as_bytes()).unwrap();
+        assert_eq!(3, tree.len());
+        assert_eq!(Some(vec!["d__Bacteria", "p__Proteobacteria", "g__Escherichia", "s__Escherichia coli"]),
+                   tree.lineage("GCF_1"));
+        assert_eq!(tree.lineage("GCF_1"), tree.lineage("RS_GCF_1"));
+        assert_eq!(Some(vec!["d__Bacteria", "p__Firmicutes", "g__Bacillus"]), tree.lineage("GCF_3"));
+        assert_eq!(None, tree.lineage("GCF_4"));
+        assert!(tree.contains_taxon("g__Escherichia"));
+        assert!(!tree.contains_taxon("s__"));
+        assert_eq!(vec!["GCA_2", "GCF_1"], tree.genomes_in("g__Escherichia"));
+        assert_eq!(vec!["GCA_2", "GCF_1", "GCF_3"], tree.genomes_in("d__Bacteria"));
+
+        let error = parse_taxonomy("GCF_1\td__Bacteria\nGCF_2\n".as_bytes()).unwrap_err();
+        assert!(error.to_string().contains("line 2"));
+        let error = parse_taxonomy("GCF_1\td__Bacteria;g__A\nGCF_2\td__Archaea;g__A\n".as_bytes())
+            .unwrap_err();
+        assert!(error.to_string().contains("g__A has more than one parent"));
+    }
+
+    #[test]
+    fn test_taxon_filter(){
+        let lineage = parse_lineage("d__Bacteria;p__Proteobacteria;g__Escherichia");
+        assert!(TaxonFilter::default().matches(&lineage));
+        let filter = TaxonFilter {
+            include: vec!["p__Proteobacteria".to_string(), "p__Firmicutes".to_string()],
+            exclude: vec!["g__Escherichia".to_string()],
+        };
+        assert!(!filter.matches(&lineage));
+        assert!(filter.matches(&parse_lineage("d__Bacteria;p__Firmicutes;g__Bacillus")));
+        assert!(!filter.matches(&parse_lineage("d__Bacteria;p__Actinobacteriota")));
+    }
+}
@@ -0,0 +1,181 @@
+use std::fmt;
+use std::fs;
+use std::io;
+use std::io::prelude::*;
+
+use compression;
+use fastq;
+use {Reader, SequenceFormat, SequenceRead, SequenceRecord};
+
+/// Something which stops a genome file from being read.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Problem {
+    Missing,
+    /// The file exists but could not be opened or decompressed.
+    Unreadable(String),
+    /// The file holds no sequence data.
+    Empty,
+    /// The first record is not valid FASTA or FASTQ.
+    Malformed(String),
+}
+
+impl fmt::Display for Problem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Problem::Missing => write!(f, "file does not exist"),
+            Problem::Unreadable(ref reason) => write!(f, "could not be read: {}", reason),
+            Problem::Empty => write!(f, "file is empty"),
+            Problem::Malformed(ref reason) => write!(f, "malformed: {}", reason),
+        }
+    }
+}
+
+/// A problem with one genome file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct GenomeFileProblem {
+    pub path: String,
+    pub problem: Problem,
+}
+
+impl fmt::Display for GenomeFileProblem {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}: {}", self.path, self.problem)
+    }
+}
+
+/// Check that the first record of a record reader has a name and sequence.
+fn check_first_record<S: SequenceRead>(mut reader: S) -> Option<Problem> {
+    let mut record = S::Record::default();
+    match reader.read_next(&mut record) {
+        Err(e) => match e.kind() {
+            io::ErrorKind::InvalidData | io::ErrorKind::UnexpectedEof => Some(Problem::Malformed(e.to_string())),
+            _ => Some(Problem::Unreadable(e.to_string())),
+        },
+        Ok(false) => Some(Problem::Empty),
+        Ok(true) if record.id().is_empty() => Some(Problem::Malformed(
+            "first record has no name".to_string())),
+        Ok(true) if record.seq().is_empty() => Some(Problem::Malformed(
+            format!("first record {} has no sequence", record.id()))),
+        Ok(true) => None,
+    }
+}
+
+/// Return the problem with a genome file, or None if it exists, can be
+/// decompressed and starts with a FASTA or FASTQ record with a name and
+/// sequence. Only the first record is read.
+pub fn check_genome_file(path: &str) -> Option<Problem> {
+    match fs::metadata(path) {
+        Err(ref e) if e.kind() == io::ErrorKind::NotFound => return Some(Problem::Missing),
+        Err(e) => return Some(Problem::Unreadable(e.to_string())),
+        Ok(ref metadata) if metadata.is_dir() => return Some(Problem::Unreadable(
+            "is a directory".to_string())),
+        Ok(ref metadata) if metadata.len() == 0 => return Some(Problem::Empty),
+        Ok(_) => {}
+    }
+
+    // Distinguish input which decompresses to nothing from input in another
+    // format before trying to parse a record.
+    let mut reader = match compression::open_file(path) {
+        Ok(reader) => io::BufReader::new(reader),
+        Err(e) => return Some(Problem::Unreadable(e.to_string())),
+    };
+    let mut has_data = false;
+    let mut buffer = [0u8; 1024];
+    loop {
+        match reader.read(&mut buffer) {
+            Ok(0) => break,
+            Ok(n) => if buffer[..n].iter().any(|b| !b.is_ascii_whitespace()) {
+                has_data = true;
+                break
+            },
+            Err(e) => return Some(Problem::Unreadable(e.to_string())),
+        }
+    }
+    if !has_data {
+        return Some(Problem::Empty)
+    }
+
+    let format = match SequenceFormat::of_file(path) {
+        Ok(format) => format,
+        Err(e) => return Some(Problem::Malformed(e.to_string())),
+    };
+    let result = match format {
+        SequenceFormat::Fasta => Reader::from_file(path).map(check_first_record),
+        SequenceFormat::Fastq => fastq::Reader::from_file(path).map(check_first_record),
+    };
+    result.unwrap_or_else(|e| Some(Problem::Unreadable(e.to_string())))
+}
+
+/// Check every genome file, returning all problems found.
+pub fn validate_genome_files(paths: &[&str]) -> Vec<GenomeFileProblem> {
+    paths.iter()
+        .filter_map(|path| check_genome_file(path).map(|problem| GenomeFileProblem {
+            path: path.to_string(),
+            problem,
+        }))
+        .collect()
+}
+
+/// Write a summary of the problems found among the given number of files,
+/// one problem per line.
+pub fn write_summary<W: io::Write>(problems: &[GenomeFileProblem], num_files: usize,
+                                   mut writer: W) -> io::Result<()> {
+    if problems.is_empty() {
+        return writeln!(writer, "All {} genome files are valid", num_files)
+    }
+    writeln!(writer, "{} of {} genome files have problems:", problems.len(), num_files)?;
+    for problem in problems {
+        writeln!(writer, "{}", problem)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_check_genome_file(){
+        assert_eq!(None, check_genome_file("tests/data/genome1.fna"));
+        assert_eq!(None, check_genome_file("tests/data/genome1.fna.xz"));
+        assert_eq!(None, check_genome_file("tests/data/reads_for_seq1.fq"));
+        assert_eq!(Some(Problem::Missing), check_genome_file("tests/data/missing.fna"));
+        match check_genome_file("tests/data") {
+            Some(Problem::Unreadable(_)) => {},
+            other => panic!("Expected a directory to be unreadable, got {:?}", other)
+        }
+
+        let dir = ::tempfile::tempdir().unwrap();
+        let write = |name: &str, contents: &[u8]| {
+            let path = dir.path().join(name);
+            fs::write(&path, contents).unwrap();
+            path.to_string_lossy().into_owned()
+        };
+        assert_eq!(Some(Problem::Empty), check_genome_file(&write("empty.fna", b"")));
+        assert_eq!(Some(Problem::Empty), check_genome_file(&write("blank.fna", b"\n\n")));
+        match check_genome_file(&write("text.fna", b"not a genome\n")) {
+            Some(Problem::Malformed(_)) => {},
+            other => panic!("Expected malformed file, got {:?}", other)
+        }
+        assert_eq!(None, check_genome_file(&write("leading_blank.fna", b"\n\r\n>seq1\nACGT\n")));
+        assert_eq!(Some(Problem::Malformed("Expected > at record start.".to_string())),
+                   check_genome_file(&write("indented.fna", b" >seq1\nACGT\n")));
+        assert_eq!(Some(Problem::Malformed("first record seq1 has no sequence".to_string())),
+                   check_genome_file(&write("no_sequence.fna", b">seq1\n>seq2\nACGT\n")));
+    }
+
+    #[test]
+    fn test_validate_genome_files(){
+        let problems = validate_genome_files(
+            &["tests/data/genome1.fna", "tests/data/missing.fna", "tests/data/7seqs.fna"]);
+        assert_eq!(vec![GenomeFileProblem {
+            path: "tests/data/missing.fna".to_string(),
+            problem: Problem::Missing,
+        }], problems);
+        let mut summary = Vec::new();
+        write_summary(&problems, 3, &mut summary).unwrap();
+        assert_eq!("1 of 3 genome files have problems:\n\
+                    tests/data/missing.fna: file does not exist\n",
+                   String::from_utf8(summary).unwrap());
+    }
+}